@@ -2,6 +2,7 @@ use rustyline::error::ReadlineError;
 use rustyline::{Editor, Result};
 use taskmaster::common::comm::send_message;
 
+#[allow(unused_must_use)]
 fn main() -> Result<()> {
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new()?;
@@ -1,27 +1,129 @@
 extern crate yaml_rust;
-use std::collections::HashMap;
 use std::env;
 use std::os::unix::net::UnixListener;
-use taskmaster::cfg::{ConfigError, Config};
+use std::path::Path;
+use std::process;
+use taskmaster::cfg::{Config, ConfigFormat, DumpFormat};
+use taskmaster::common::comm::SOCKET_PATH;
 use taskmaster::daemon::Daemon;
+use taskmaster::{supervisord, systemd};
 
-fn main() -> Result<(), ConfigError> {
-    //TODO: get config path from argv
+const USAGE: &str = "usage: taskmasterd [--check | --dump-config [--format yaml|json] | --export-systemd dir] \
+                     [--config-format yaml|toml|json] [config]\n       \
+                     taskmasterd --import-supervisord supervisord.conf";
+
+/// What to do with the config.
+enum Mode {
+    Run,
+    /// Validate it, with the `strict` checks, and exit.
+    Check,
+    /// Print it fully resolved and exit.
+    Dump,
+    /// Convert a supervisord config and exit.
+    Import,
+    /// Write systemd units to a directory and exit.
+    ExportSystemd(String),
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_args(args: &[String]) -> (Mode, DumpFormat, String, Option<ConfigFormat>) {
+    let mut mode = Mode::Run;
+    let mut format = DumpFormat::Yaml;
+    let mut cfg_path = None;
+    let mut cfg_format = None;
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => mode = Mode::Check,
+            "--dump-config" => mode = Mode::Dump,
+            "--import-supervisord" if cfg_path.is_none() => match args.next() {
+                Some(p) => {
+                    mode = Mode::Import;
+                    cfg_path = Some(p.to_string());
+                }
+                None => usage(),
+            },
+            "--export-systemd" => match args.next() {
+                Some(dir) => mode = Mode::ExportSystemd(dir.to_string()),
+                None => usage(),
+            },
+            "--format" => match args.next().map(|f| f.parse::<DumpFormat>()) {
+                Some(Ok(f)) => format = f,
+                _ => usage(),
+            },
+            "--config-format" => match args.next().map(|f| f.parse::<ConfigFormat>()) {
+                Some(Ok(f)) => cfg_format = Some(f),
+                _ => usage(),
+            },
+            a if a.starts_with('-') => usage(),
+            a if cfg_path.is_none() => cfg_path = Some(a.to_string()),
+            _ => usage(),
+        }
+    }
+    let cfg_path = cfg_path.unwrap_or_else(|| "taskmaster.yaml".to_string());
+    (mode, format, cfg_path, cfg_format)
+}
+
+/// Print the taskmaster version of a supervisord config, with warnings for
+/// what could not be converted.
+fn import_supervisord(path: &str) -> ! {
+    let ini = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let (yaml, warnings) = supervisord::import(&ini);
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    print!("{}", yaml);
+    process::exit(0);
+}
+
+fn main() {
     let args = env::args().collect::<Vec<String>>();
-    let cfg_path = match args.get(1) {
-        Some(a) => a,
-        None => "taskmaster.yaml",
+    let (mode, format, cfg_path, cfg_format) = parse_args(&args);
+    if let Mode::Import = mode {
+        import_supervisord(&cfg_path);
+    }
+    let cfg_format = cfg_format.unwrap_or_else(|| ConfigFormat::from_path(&cfg_path));
+    // `--check` reports unknown keys and missing paths even without `strict`
+    let conf = match mode {
+        Mode::Check => Config::check_file_as(&cfg_path, cfg_format),
+        _ => Config::from_file_as(&cfg_path, cfg_format),
+    };
+    let conf = match conf {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     };
-    let path = "taskmaster.socket";
+    match mode {
+        Mode::Check => return println!("{}: ok", cfg_path),
+        Mode::Dump => return print!("{}", conf.dump(format)),
+        Mode::ExportSystemd(dir) => match systemd::export(&conf, Path::new(&dir)) {
+            Ok(paths) => return paths.iter().for_each(|p| println!("{}", p.display())),
+            Err(e) => {
+                eprintln!("{}: {}", dir, e);
+                process::exit(1);
+            }
+        },
+        Mode::Run | Mode::Import => (),
+    }
+    let path = SOCKET_PATH;
     if std::fs::metadata(path).is_ok() {
         println!("A socket is already present. Deleting...");
         std::fs::remove_file(path).expect("could not delete previous socket at {:?}");
     }
-    let mut daemon: Daemon = Daemon {
-        conf: Config::from_file(cfg_path)?,
-        listener: UnixListener::bind(path).expect("failed to open stream"),
-        proc_list: HashMap::new(),
-    };
-    Ok(daemon.run())
+    let listener = UnixListener::bind(path).expect("failed to open stream");
+    let mut daemon = Daemon::new(conf, listener, cfg_path, cfg_format);
+    daemon.run();
     //TODO: test behavior with invalid config file
 }
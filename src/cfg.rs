@@ -1,13 +1,28 @@
+use chrono::{DateTime, Local};
 use core::fmt;
+use croner::Cron;
 use nix::sys::signal::Signal;
-use std::collections::HashMap;
+use regex::{Captures, Regex};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::error::Error;
 use std::fs::{self, File};
+use std::path::Path;
 use std::process::Stdio;
 use std::str::FromStr;
-use yaml_rust::{Yaml, YamlLoader};
+use std::sync::LazyLock;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
 
 const DFLT_NUMPROCS: i64 = 1;
+const DFLT_NUMPROCS_START: i64 = 0;
+const DFLT_PROCESS_NAME: &str = "%(program_name)s";
+const DFLT_NUMBERED_PROCESS_NAME: &str = "%(program_name)s%(process_num)d";
 const DFLT_UMASK: u32 = 0o022;
 const DFLT_CWD: Option<String> = None;
 const DFLT_AUTOSTART: bool = true;
@@ -19,59 +34,980 @@ const DFLT_STOPSIGNAL: &str = "TERM";
 const DFLT_STOPTIME: i64 = 10;
 const DFLT_STDOUT: &str = "AUTO";
 const DFLT_STDERR: &str = "AUTO";
+const DFLT_TYPE: ProcessType = ProcessType::Simple;
+const DFLT_PRIORITY: i64 = 999;
+const DFLT_READY: ReadyPolicy = ReadyPolicy::Starttime;
+const DFLT_OVERLAP: OverlapPolicy = OverlapPolicy::Skip;
+const DFLT_HEALTH_INTERVAL: i64 = 10;
+const DFLT_HEALTH_TIMEOUT: i64 = 5;
+const DFLT_HEALTH_RETRIES: i64 = 3;
+
+/// Fields merged key by key with those of `defaults` and templates instead
+/// of being replaced.
+const MERGED_FIELDS: [&str; 2] = ["env", "labels"];
+
+const TOP_LEVEL_FIELDS: [&str; 7] = [
+    "programs", "groups", "include", "defaults", "templates", "strict", "watch_config",
+];
+const PROGRAM_FIELDS: [&str; 28] = [
+    "cmd", "numprocs", "numprocs_start", "process_name", "umask", "workingdir", "autostart",
+    "autorestart", "exitcodes", "startretries", "starttime", "stopsignal", "stoptime", "stdout",
+    "stderr", "env", "env_file", "type", "pidfile", "healthcheck", "ready", "watchdog", "depends_on",
+    "priority", "labels", "extends", "schedule", "overlap",
+];
+/// Fields used when spawning a process, that only apply to it once restarted.
+const RESTART_FIELDS: [&str; 14] = [
+    "name", "cmd", "umask", "workingdir", "stdout", "stderr", "env", "type", "pidfile", "ready",
+    "watchdog", "group", "group_name", "process_num",
+];
+/// `${VAR}` and `${VAR:-default}` references, and the `$${` escape.
+static ENV_REF: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$\$\{|\$\{(\w+)(:-([^}]*))?\}").unwrap());
+/// `%(name)s` and `%(name)d` placeholders, with an optional padded width.
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"%\((\w+)\)(0?)(\d*)([sd])").unwrap());
+
+const HEALTHCHECK_FIELDS: [&str; 7] = ["cmd", "tcp", "unix", "http", "interval", "timeout", "retries"];
+/// Fields holding numbers or booleans, typed again after `${VAR}` expansion.
+const TYPED_FIELDS: [&str; 16] = [
+    "numprocs", "numprocs_start", "umask", "autostart", "exitcodes", "startretries", "starttime",
+    "stoptime", "watchdog", "priority", "tcp", "interval", "timeout", "retries", "strict",
+    "watch_config",
+];
 
 #[derive(Debug)]
 pub struct Config {
     pub programs: HashMap<String, ProgramConfig>,
+    /// Instances of the programs of each group, every program being
+    /// implicitly a group of its own instances.
+    pub groups: HashMap<String, Vec<String>>,
+    /// Config of each program before expansion, with `defaults` and
+    /// templates applied.
+    sources: HashMap<String, Source>,
+    /// Files the config was read from, the main one first.
+    pub files: Vec<String>,
+    /// Patterns of the included files, from the root.
+    pub includes: Vec<String>,
+    /// Reload the config when one of its files changes.
+    pub watch_config: bool,
 }
 
 impl Config {
-    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+    /// Read a config file, in the format given by its extension.
+    pub fn from_file(path: &str) -> Result<Config, ConfigErrors> {
+        Config::from_file_as(path, ConfigFormat::from_path(path))
+    }
+
+    pub fn from_file_as(path: &str, format: ConfigFormat) -> Result<Config, ConfigErrors> {
+        Config::read_file(path, format, false)
+    }
+
+    /// Load a config as `strict: true` would, whatever it sets, to report
+    /// every problem of it.
+    pub fn check_file_as(path: &str, format: ConfigFormat) -> Result<Config, ConfigErrors> {
+        Config::read_file(path, format, true)
+    }
+
+    fn read_file(path: &str, format: ConfigFormat, strict: bool) -> Result<Config, ConfigErrors> {
         let yaml_str = match fs::read_to_string(path) {
             Ok(f) => Ok(f),
-            Err(e) => Err(ConfigError::from_unreadable_file(e)),
+            Err(e) => Err(ConfigError::from_unreadable_file(e).in_file(path)),
         }?;
-        match Config::from_str(&yaml_str) {
+        let here = match Path::new(path).parent().map(fs::canonicalize) {
+            Some(Ok(dir)) => dir.to_string_lossy().to_string(),
+            _ => current_dir(),
+        };
+        Config::parse(&yaml_str, format, path, here, strict)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(str: &str) -> Result<Config, ConfigErrors> {
+        Config::parse(str, ConfigFormat::Yaml, "<string>", current_dir(), false)
+    }
+
+    /// Load a config, locating each of its problems in the file it comes
+    /// from.
+    fn parse(str: &str, format: ConfigFormat, path: &str, here: String, strict: bool) -> Result<Config, ConfigErrors> {
+        let mut origins = Origins::default();
+        match Config::load(str, format, path, here, strict, &mut origins) {
             Ok(c) => Ok(c),
-            Err(e) => Err(ConfigError::from_invalid_cfg_file(e)),
+            Err(e) => Err(ConfigErrors(e.0.into_iter().map(|e| origins.locate(e)).collect())),
         }
     }
 
-    pub fn from_str(str: &str) -> Result<Config, ConfigError> {
-        match YamlLoader::load_from_str(str) {
-            Ok(yaml) => Config::from_yaml(&yaml[0]),
-            Err(e) => Err(ConfigError::from_invalid_yaml(e)),
+    fn load(str: &str, format: ConfigFormat, path: &str, here: String, strict: bool, origins: &mut Origins) -> Result<Config, ConfigErrors> {
+        origins.add_file(path, str, format);
+        let mut errors = ConfigErrors::default();
+        let yaml = expand_env(&load_doc(str, format)?, &mut Vec::new(), &mut errors);
+        let yaml = merge_includes(&yaml, &here, origins, &mut errors);
+        match errors.check(Config::from_yaml(&yaml, here, &origins.programs, strict).map(Some), None) {
+            Some(mut conf) if errors.0.is_empty() => {
+                conf.files = origins.files.clone();
+                conf.includes = origins.includes.clone();
+                Ok(conf)
+            }
+            _ => Err(errors),
         }
     }
 
-    fn from_yaml(yaml: &Yaml) -> Result<Config, ConfigError> {
+    /// Read a config whose programs defined outside of the main file are
+    /// in `included`, with their file. `strict` forces the strict checks on.
+    fn from_yaml(yaml: &Yaml, here: String, included: &HashMap<String, String>, strict: bool) -> Result<Config, ConfigErrors> {
         let mut programs: HashMap<String, ProgramConfig> = HashMap::new();
+        let mut instances: HashMap<String, Vec<String>> = HashMap::new();
+        let mut sources: HashMap<String, Source> = HashMap::new();
+        let mut errors = ConfigErrors::default();
+        // with `strict`, unknown keys and missing paths are errors
+        let strict = errors.check(get_bool_field(yaml, "strict", false), false) || strict;
+        if strict {
+            errors.check(check_keys(yaml, &TOP_LEVEL_FIELDS, ""), ());
+            errors.check(check_keys(&yaml["defaults"], &PROGRAM_FIELDS, "defaults"), ());
+        }
+        if let Some(templates) = yaml["templates"].as_hash().filter(|_| strict) {
+            for (name, template) in templates {
+                let section = format!("templates.{}", name.as_str().unwrap_or_default());
+                errors.check(check_keys(template, &PROGRAM_FIELDS, &section), ());
+            }
+        }
+        let watch_config = errors.check(get_bool_field(yaml, "watch_config", false), false);
         let yprog = match yaml["programs"].as_hash() {
-            Some(y) => Ok(y),
-            None => Err(ConfigError::new("no programs field found")),
-        }?;
+            Some(y) => y,
+            None => {
+                errors.push(ConfigError::from_missing_field("programs"));
+                return Err(errors);
+            }
+        };
+        let named_groups = named_groups(yaml);
         for (yname, yconf) in yprog.into_iter() {
-            let numprocs = get_num_field(yconf, "numprocs", 1)?;
             let base_name = match yname.as_str() {
-                Some(n) => Ok(n),
-                None => Err(ConfigError::new("program name is not a string")),
+                Some(n) => n,
+                None => {
+                    errors.push(ConfigError::from_invalid_name("program"));
+                    continue;
+                }
+            };
+            let here = match included.get(base_name).and_then(|f| Path::new(f).parent()) {
+                Some(dir) => dir.to_string_lossy().to_string(),
+                None => here.clone(),
+            };
+            let group = named_groups.get(base_name).cloned().unwrap_or(base_name.to_string());
+            let (source, confs) = match load_program(base_name, yconf, yaml, here, group, strict) {
+                Ok(p) => p,
+                Err(e) => {
+                    errors.extend(e.in_program(base_name));
+                    continue;
+                }
+            };
+            for conf in confs {
+                if let Some(other) = programs.get(&conf.name) {
+                    errors.push(ConfigError::from_name_collision(&conf.name, &other.group).in_program(base_name));
+                    continue;
+                }
+                if strict {
+                    errors.check(conf.check_paths().map_err(|e| e.in_program(base_name)), ());
+                }
+                instances.entry(base_name.to_string()).or_default().push(conf.name.clone());
+                programs.insert(conf.name.clone(), conf);
+            }
+            sources.insert(base_name.to_string(), source);
+        }
+        for conf in programs.values_mut() {
+            let deps = expand_dependencies(conf, &instances).map_err(|e| e.in_program(&conf.group));
+            conf.depends_on = errors.check(deps, Vec::new());
+        }
+        let groups = errors.check(get_groups(yaml, "groups", instances), HashMap::new());
+        if !errors.0.is_empty() {
+            return Err(errors);
+        }
+        let conf = Config {
+            programs,
+            groups,
+            sources,
+            files: Vec::new(),
+            includes: Vec::new(),
+            watch_config,
+        };
+        conf.check_cycles()?;
+        Ok(conf)
+    }
+
+    fn check_cycles(&self) -> Result<(), ConfigError> {
+        let mut done = HashSet::new();
+        let mut names: Vec<&String> = self.programs.keys().collect();
+        names.sort();
+        for name in names {
+            self.find_cycle(name, &mut Vec::new(), &mut done)?;
+        }
+        Ok(())
+    }
+
+    fn find_cycle(&self, name: &str, path: &mut Vec<String>, done: &mut HashSet<String>) -> Result<(), ConfigError> {
+        if let Some(i) = path.iter().position(|n| n == name) {
+            let mut cycle = path[i..].to_vec();
+            cycle.push(name.to_string());
+            return Err(ConfigError::from_dependency_cycle(&cycle));
+        }
+        if done.contains(name) {
+            return Ok(());
+        }
+        path.push(name.to_string());
+        for dep in &self.programs[name].depends_on {
+            self.find_cycle(dep, path, done)?;
+        }
+        path.pop();
+        done.insert(name.to_string());
+        Ok(())
+    }
+
+    /// Config shared by the instances of a program, with `placeholder` in
+    /// place of their process number.
+    pub fn template(&self, program: &str, placeholder: &str) -> Result<ProgramConfig, ConfigErrors> {
+        let source = match self.sources.get(program) {
+            Some(s) => s,
+            None => return Err(ConfigError::from_unknown_program(program).into()),
+        };
+        let vars = ProcessVars {
+            program_name: program,
+            process_num: 0,
+            group_name: &source.group,
+            here: &source.here,
+            num_placeholder: Some(placeholder),
+        };
+        let mut conf = ProgramConfig::from_yaml(&source.yaml, program.to_string(), &vars).map_err(|e| e.in_program(program))?;
+        conf.group = program.to_string();
+        conf.group_name = source.group.clone();
+        Ok(conf)
+    }
+
+    /// Change the number of instances of a program. Instances keep their
+    /// process number. Returns the instance names before and after.
+    pub fn scale(&mut self, program: &str, numprocs: i64) -> Result<(Vec<String>, Vec<String>), ConfigErrors> {
+        let source = match self.sources.get(program) {
+            Some(s) => s,
+            None => return Err(ConfigError::from_unknown_program(program).into()),
+        };
+        let confs = expand_program(program, source, numprocs).map_err(|e| e.in_program(program))?;
+        let old = self.groups[program].clone();
+        if let Some(c) = confs.iter().find(|c| !old.contains(&c.name) && self.programs.contains_key(&c.name)) {
+            let other = &self.programs[&c.name].group;
+            return Err(ConfigError::from_name_collision(&c.name, other).in_program(program).into());
+        }
+        let depends_on = self.programs[&old[0]].depends_on.clone();
+        for name in &old {
+            self.programs.remove(name);
+        }
+        let new: Vec<String> = confs.iter().map(|c| c.name.clone()).collect();
+        for mut conf in confs {
+            conf.depends_on = depends_on.clone();
+            self.programs.insert(conf.name.clone(), conf);
+        }
+        for members in self.groups.values_mut() {
+            replace_instances(members, &old, &new);
+        }
+        for conf in self.programs.values_mut() {
+            replace_instances(&mut conf.depends_on, &old, &new);
+        }
+        Ok((old, new))
+    }
+
+    /// Effective config of the given programs, or of all of them, as YAML
+    /// with `defaults` and templates applied.
+    pub fn effective(&self, programs: &[&str]) -> Result<String, ConfigError> {
+        let mut names: Vec<&String> = self.sources.keys().collect();
+        names.sort();
+        if let Some(p) = programs.iter().find(|p| !self.sources.contains_key(**p)) {
+            return Err(ConfigError::from_unknown_program(p));
+        }
+        let mut yprog = Hash::new();
+        for name in names {
+            if programs.is_empty() || programs.contains(&name.as_str()) {
+                yprog.insert(Yaml::String(name.clone()), escape_yaml_env_refs(&self.sources[name].yaml));
+            }
+        }
+        let mut doc = Hash::new();
+        doc.insert(Yaml::String("programs".to_string()), Yaml::Hash(yprog));
+        let mut out = String::new();
+        match YamlEmitter::new(&mut out).dump(&Yaml::Hash(doc)) {
+            Ok(()) => Ok(out + "\n"),
+            Err(e) => Err(ConfigError::new(ErrorKind::InvalidValue, &format!("failed to emit config: {:?}", e))),
+        }
+    }
+
+    /// Fully resolved config: one entry per instance, with defaults,
+    /// templates and interpolation applied.
+    pub fn dump(&self, format: DumpFormat) -> String {
+        // the groups of the instances of each program are not written, as
+        // the instances are
+        let groups: HashMap<&String, &Vec<String>> = self
+            .groups
+            .iter()
+            .filter(|(name, _)| !self.programs.values().any(|p| p.group == **name))
+            .collect();
+        let mut value = json!({ "programs": self.programs, "groups": groups });
+        escape_env_refs(&mut value);
+        match format {
+            DumpFormat::Json => serde_json::to_string_pretty(&value).unwrap_or_default() + "\n",
+            DumpFormat::Yaml => {
+                let mut out = String::new();
+                let _ = YamlEmitter::new(&mut out).dump(&json_to_yaml(&value));
+                out + "\n"
+            }
+        }
+    }
+
+    /// Names of all programs, each one after its dependencies, otherwise
+    /// sorted by priority then name.
+    pub fn start_order(&self) -> Vec<String> {
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for name in self.by_priority(self.programs.keys()) {
+            self.visit(&name, &mut visited, &mut order);
+        }
+        order
+    }
+
+    fn by_priority<'a, I: Iterator<Item = &'a String>>(&self, names: I) -> Vec<String> {
+        let mut names: Vec<String> = names.cloned().collect();
+        names.sort_by(|a, b| {
+            (self.programs[a].priority, a).cmp(&(self.programs[b].priority, b))
+        });
+        names
+    }
+
+    fn visit(&self, name: &str, visited: &mut HashSet<String>, order: &mut Vec<String>) {
+        if !visited.insert(name.to_string()) {
+            return;
+        }
+        for dep in self.by_priority(self.programs[name].depends_on.iter()) {
+            self.visit(&dep, visited, order);
+        }
+        order.push(name.to_string());
+    }
+}
+
+/// Reject the keys of a hash which are not in `known`, suggesting the
+/// closest known one.
+fn check_keys(yaml: &Yaml, known: &[&'static str], section: &str) -> Result<(), ConfigErrors> {
+    let hash = match yaml.as_hash() {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+    let mut errors = ConfigErrors::default();
+    for key in hash.keys() {
+        let key = key.as_str().unwrap_or_default();
+        if known.contains(&key) {
+            continue;
+        }
+        let suggestion = known
+            .iter()
+            .map(|k| (edit_distance(key, k), *k))
+            .filter(|(d, k)| *d <= 2.max(k.len() / 3))
+            .min();
+        errors.push(ConfigError::from_unknown_key(key, suggestion.map(|(_, k)| k)).within(section));
+    }
+    errors.into_result(())
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb { prev } else { 1 + prev.min(row[j]).min(cur) };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Apply to a program or template the one it `extends`, or the top-level
+/// `defaults`, its own fields taking precedence.
+fn resolve_program(yconf: &Yaml, yaml: &Yaml, chain: &mut Vec<String>) -> Result<Yaml, ConfigError> {
+    let base = match &yconf["extends"] {
+        Yaml::BadValue => yaml["defaults"].clone(),
+        Yaml::String(name) => {
+            if chain.contains(name) {
+                chain.push(name.clone());
+                return Err(ConfigError::from_template_cycle(chain));
+            }
+            let template = match &yaml["templates"][name.as_str()] {
+                Yaml::BadValue => Err(ConfigError::from_unknown_template(name)),
+                t => Ok(t),
             }?;
-            for i in 0..numprocs {
-                let name = gen_name(numprocs, base_name, i);
-                let conf = ProgramConfig::from_yaml(yconf, name.clone())?;
-                programs.insert(name.clone(), conf);
+            chain.push(name.clone());
+            resolve_program(template, yaml, chain)?
+        }
+        _ => return Err(ConfigError::from_not_string("extends")),
+    };
+    Ok(merge_yaml(&base, yconf))
+}
+
+fn merge_yaml(base: &Yaml, over: &Yaml) -> Yaml {
+    let (mut merged, over) = match (base, over) {
+        (Yaml::Hash(b), Yaml::Hash(o)) => (b.clone(), o),
+        _ => return over.clone(),
+    };
+    for (k, v) in over {
+        let value = match (k.as_str(), merged.get(k)) {
+            (Some("extends"), _) => continue,
+            (Some(f), Some(b)) if MERGED_FIELDS.contains(&f) => merge_yaml(b, v),
+            _ => v.clone(),
+        };
+        merged.insert(k.clone(), value);
+    }
+    Yaml::Hash(merged)
+}
+
+/// Syntax of a config file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Format of a file from its extension, YAML by default.
+    pub fn from_path(path: &str) -> ConfigFormat {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ConfigFormat, Self::Err> {
+        match s {
+            "yaml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigFormat::Yaml => write!(f, "YAML"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+/// Load a config document as YAML, which TOML and JSON documents map to, so
+/// that every format goes through the same checks.
+fn load_doc(str: &str, format: ConfigFormat) -> Result<Yaml, ConfigError> {
+    match format {
+        ConfigFormat::Yaml => match YamlLoader::load_from_str(str) {
+            Ok(mut docs) if !docs.is_empty() => Ok(docs.swap_remove(0)),
+            Ok(_) => Ok(Yaml::BadValue),
+            Err(e) => Err(ConfigError::from_invalid_syntax(format, &e.to_string(), Some(line_col(e.marker())))),
+        },
+        ConfigFormat::Toml => match toml::from_str::<Value>(str) {
+            Ok(v) => Ok(umask_digits(&json_to_yaml(&v), "")),
+            Err(e) => {
+                let position = e.line_col().map(|(l, c)| (l as u32 + 1, c as u32 + 1));
+                Err(ConfigError::from_invalid_syntax(format, &e.to_string(), position))
+            }
+        },
+        ConfigFormat::Json => match serde_json::from_str::<Value>(str) {
+            Ok(v) => Ok(umask_digits(&json_to_yaml(&v), "")),
+            Err(e) => {
+                let position = Some((e.line() as u32, e.column() as u32));
+                Err(ConfigError::from_invalid_syntax(format, &e.to_string(), position))
+            }
+        },
+    }
+}
+
+/// TOML and JSON have a umask as the mode itself, as in `umask = 0o022`,
+/// where YAML has its octal digits: rewrite it as the digits.
+fn umask_digits(yaml: &Yaml, key: &str) -> Yaml {
+    match yaml {
+        Yaml::Integer(n) if key == "umask" && *n >= 0 => Yaml::Integer(format!("{:o}", n).parse().unwrap_or(*n)),
+        Yaml::Hash(h) if !MERGED_FIELDS.contains(&key) => Yaml::Hash(
+            h.iter()
+                .map(|(k, v)| (k.clone(), umask_digits(v, k.as_str().unwrap_or_default())))
+                .collect(),
+        ),
+        _ => yaml.clone(),
+    }
+}
+
+/// Merge the `programs` of the files matched by the `include` patterns,
+/// relative to the directory of the main file, into the main config.
+fn merge_includes(yaml: &Yaml, here: &str, origins: &mut Origins, errors: &mut ConfigErrors) -> Yaml {
+    let patterns = errors.check(get_str_vec_field(yaml, "include", Vec::new()), Vec::new());
+    if patterns.is_empty() {
+        return yaml.clone();
+    }
+    let mut programs = match &yaml["programs"] {
+        Yaml::Hash(h) => h.clone(),
+        _ => Hash::new(),
+    };
+    for pattern in patterns {
+        let full = Path::new(here).join(&pattern);
+        origins.includes.push(full.to_string_lossy().to_string());
+        let paths = match glob::glob(&full.to_string_lossy()) {
+            Ok(p) => p,
+            Err(_) => {
+                errors.push(ConfigError::from_invalid_include(&pattern));
+                continue;
+            }
+        };
+        for path in paths.flatten() {
+            let path = path.to_string_lossy().to_string();
+            let mut file_errors = ConfigErrors::default();
+            let included = match read_include(&path, origins) {
+                Ok(y) => expand_env(&y, &mut Vec::new(), &mut file_errors),
+                Err(e) => {
+                    errors.push(e.in_file(&path));
+                    continue;
+                }
+            };
+            for e in file_errors.0 {
+                errors.push(e.in_file(&path));
+            }
+            let yprog = match &included["programs"] {
+                Yaml::Hash(h) => h,
+                Yaml::BadValue => continue,
+                _ => {
+                    errors.push(ConfigError::from_not_hash("programs").in_file(&path));
+                    continue;
+                }
+            };
+            for (yname, conf) in yprog {
+                let name = yname.as_str().unwrap_or_default();
+                if programs.contains_key(yname) {
+                    let first = origins.programs.get(name).unwrap_or(&origins.files[0]);
+                    errors.push(ConfigError::from_duplicate_program(first).in_program(name).in_file(&path));
+                    continue;
+                }
+                origins.programs.insert(name.to_string(), path.clone());
+                programs.insert(yname.clone(), conf.clone());
             }
         }
-        Ok(Config { programs })
     }
+    let mut merged = match yaml {
+        Yaml::Hash(h) => h.clone(),
+        _ => Hash::new(),
+    };
+    merged.insert(Yaml::String("programs".to_string()), Yaml::Hash(programs));
+    Yaml::Hash(merged)
 }
 
-#[derive(Debug, Clone)]
+/// Replace the `${VAR}` and `${VAR:-default}` references in the strings of
+/// a document with the environment of the daemon, `$${` standing for `${`.
+/// Values of number and boolean fields made of a single reference are typed
+/// again.
+fn expand_env(yaml: &Yaml, path: &mut Vec<String>, errors: &mut ConfigErrors) -> Yaml {
+    match yaml {
+        Yaml::String(s) => {
+            let mut missing = None;
+            let expanded = ENV_REF.replace_all(s, |caps: &Captures| {
+                let var = match caps.get(1) {
+                    Some(v) => v.as_str(),
+                    None => return "${".to_string(),
+                };
+                match (env::var(var), caps.get(3)) {
+                    (Ok(v), None) => v,
+                    (Ok(v), Some(_)) if !v.is_empty() => v,
+                    (_, Some(default)) => default.as_str().to_string(),
+                    (Err(_), None) => {
+                        missing = Some(var.to_string());
+                        String::new()
+                    }
+                }
+            });
+            if let Some(var) = missing {
+                let e = ConfigError::from_undefined_variable(&var);
+                errors.push(match path.as_slice() {
+                    [programs, program, field @ ..] if programs == "programs" => match field {
+                        [] => e.in_program(program),
+                        _ => e.at(&field.join(".")).in_program(program),
+                    },
+                    _ => e.at(&path.join(".")),
+                });
+            }
+            let single = ENV_REF.find(s).is_some_and(|m| m.len() == s.len() && m.as_str() != "$${");
+            // the field of an array element is its parent, as in `exitcodes.0`
+            let field = match path.as_slice() {
+                [.., parent, last] if last.parse::<usize>().is_ok() => Some(parent),
+                [.., last] => Some(last),
+                [] => None,
+            };
+            let in_map = path.len() > 1 && MERGED_FIELDS.contains(&path[path.len() - 2].as_str());
+            let typed = field.is_some_and(|f| TYPED_FIELDS.contains(&f.as_str())) && !in_map;
+            match Yaml::from_str(&expanded) {
+                y @ (Yaml::Integer(_) | Yaml::Real(_) | Yaml::Boolean(_)) if single && typed => y,
+                _ => Yaml::String(expanded.to_string()),
+            }
+        }
+        Yaml::Array(a) => Yaml::Array(
+            a.iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    path.push(i.to_string());
+                    let v = expand_env(v, path, errors);
+                    path.pop();
+                    v
+                })
+                .collect(),
+        ),
+        Yaml::Hash(h) => Yaml::Hash(
+            h.iter()
+                .map(|(k, v)| {
+                    path.push(k.as_str().unwrap_or_default().to_string());
+                    let v = expand_env(v, path, errors);
+                    path.pop();
+                    (k.clone(), v)
+                })
+                .collect(),
+        ),
+        _ => yaml.clone(),
+    }
+}
+
+fn read_include(path: &str, origins: &mut Origins) -> Result<Yaml, ConfigError> {
+    let str = match fs::read_to_string(path) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(ConfigError::from_unreadable_file(e)),
+    }?;
+    let format = ConfigFormat::from_path(path);
+    origins.add_file(path, &str, format);
+    load_doc(&str, format)
+}
+
+/// Files a config was read from, to locate its problems.
+#[derive(Default)]
+struct Origins {
+    files: Vec<String>,
+    includes: Vec<String>,
+    /// File of each program defined outside of the main file.
+    programs: HashMap<String, String>,
+    markers: HashMap<String, Markers>,
+}
+
+impl Origins {
+    fn add_file(&mut self, path: &str, str: &str, format: ConfigFormat) {
+        self.files.push(path.to_string());
+        // JSON being YAML, only TOML files cannot be scanned for positions
+        if format != ConfigFormat::Toml {
+            self.markers.insert(path.to_string(), Markers::scan(str));
+        }
+    }
+
+    /// Fill in the file and position of a problem from its program and
+    /// field.
+    fn locate(&self, mut e: ConfigError) -> ConfigError {
+        if e.file.is_none() {
+            let file = e.program.as_ref().and_then(|p| self.programs.get(p));
+            e.file = file.or(self.files.first()).cloned();
+        }
+        if e.position.is_none() {
+            if let Some(markers) = e.file.as_ref().and_then(|f| self.markers.get(f)) {
+                e.position = markers.find(&e.key_path());
+            }
+        }
+        e
+    }
+}
+
+/// Line and column of a marker, both from 1.
+fn line_col(m: &Marker) -> (u32, u32) {
+    (m.line() as u32, m.col() as u32 + 1)
+}
+
+/// Position of every key of a YAML document, by path from the root.
+#[derive(Default)]
+struct Markers {
+    keys: HashMap<Vec<String>, Marker>,
+    path: Vec<String>,
+    stack: Vec<Node>,
+}
+
+/// Collection being parsed, with the key of the value being parsed for maps.
+enum Node {
+    Map(Option<String>),
+    Seq(usize),
+}
+
+impl Markers {
+    fn scan(str: &str) -> Markers {
+        let mut markers = Markers::default();
+        let _ = Parser::new(str.chars()).load(&mut markers, false);
+        markers
+    }
+
+    /// Line and column of the deepest key of `path` found.
+    fn find(&self, path: &[String]) -> Option<(u32, u32)> {
+        (1..=path.len())
+            .rev()
+            .find_map(|n| self.keys.get(&path[..n]))
+            .map(line_col)
+    }
+
+    fn segment(&self) -> String {
+        match self.stack.last() {
+            Some(Node::Map(Some(k))) => k.clone(),
+            Some(Node::Seq(i)) => i.to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Node::Map(key)) => *key = None,
+            Some(Node::Seq(i)) => *i += 1,
+            None => (),
+        }
+    }
+}
+
+impl MarkedEventReceiver for Markers {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                if !self.stack.is_empty() {
+                    self.path.push(self.segment());
+                }
+                self.stack.push(match ev {
+                    Event::MappingStart(_) => Node::Map(None),
+                    _ => Node::Seq(0),
+                });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                if !self.stack.is_empty() {
+                    self.path.pop();
+                    self.value_done();
+                }
+            }
+            Event::Scalar(key, ..) if matches!(self.stack.last(), Some(Node::Map(None))) => {
+                let mut path = self.path.clone();
+                path.push(key.clone());
+                self.keys.insert(path, mark);
+                self.stack.pop();
+                self.stack.push(Node::Map(Some(key)));
+            }
+            Event::Scalar(..) | Event::Alias(_) => self.value_done(),
+            _ => (),
+        }
+    }
+}
+
+fn current_dir() -> String {
+    match env::current_dir() {
+        Ok(d) => d.to_string_lossy().to_string(),
+        Err(_) => ".".to_string(),
+    }
+}
+
+/// Values of the `%(name)s` placeholders of an instance config.
+pub struct ProcessVars<'a> {
+    pub program_name: &'a str,
+    pub process_num: i64,
+    pub group_name: &'a str,
+    pub here: &'a str,
+    /// Text to leave in place of `%(process_num)`, instead of the number.
+    pub num_placeholder: Option<&'a str>,
+}
+
+impl ProcessVars<'_> {
+    /// Replace the `%(name)s` and `%(name)d` placeholders of a field value,
+    /// with an optional zero padded width as in `%(process_num)02d`.
+    pub fn interpolate(&self, value: &str, field: &str) -> Result<String, ConfigError> {
+        let mut error = None;
+        let result = PLACEHOLDER.replace_all(value, |caps: &Captures| {
+            let width = caps[3].parse::<usize>().unwrap_or(0);
+            let pad = &caps[2] == "0";
+            if let (Some(placeholder), "process_num") = (self.num_placeholder, &caps[1]) {
+                // the placeholder stands for a number that cannot be padded
+                if width > 0 {
+                    error = Some(ConfigError::from_padded_placeholder(field, &caps[0]));
+                }
+                return placeholder.to_string();
+            }
+            match (&caps[1], &caps[4]) {
+                ("process_num", _) if pad => format!("{:0width$}", self.process_num, width = width),
+                ("process_num", _) => format!("{:width$}", self.process_num, width = width),
+                ("program_name", "s") => format!("{:width$}", self.program_name, width = width),
+                ("group_name", "s") => format!("{:width$}", self.group_name, width = width),
+                ("here", "s") => format!("{:width$}", self.here, width = width),
+                _ => {
+                    error = Some(ConfigError::from_invalid_interpolation(field, &caps[0]));
+                    String::new()
+                }
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(result.to_string()),
+        }
+    }
+}
+
+/// Resolve the config of a program and create its instances, rejecting
+/// unknown keys if `strict`.
+fn load_program(
+    program: &str,
+    yconf: &Yaml,
+    yaml: &Yaml,
+    here: String,
+    group: String,
+    strict: bool,
+) -> Result<(Source, Vec<ProgramConfig>), ConfigErrors> {
+    let yconf = resolve_program(yconf, yaml, &mut Vec::new())?;
+    let mut errors = ConfigErrors::default();
+    if strict {
+        errors.check(check_keys(&yconf, &PROGRAM_FIELDS, ""), ());
+        errors.check(check_keys(&yconf["healthcheck"], &HEALTHCHECK_FIELDS, "healthcheck"), ());
+    }
+    let numprocs = errors.check(get_num_field(&yconf, "numprocs", DFLT_NUMPROCS), DFLT_NUMPROCS);
+    let source = Source { yaml: yconf, here, group };
+    let confs = errors.check(expand_program(program, &source, numprocs), Vec::new());
+    errors.into_result((source, confs))
+}
+
+/// Config of a program before expansion, with what its placeholders need.
+#[derive(Debug)]
+struct Source {
+    yaml: Yaml,
+    /// Directory of the file the program is defined in.
+    here: String,
+    /// Named group the program belongs to, or the program itself.
+    group: String,
+}
+
+/// Named group of each program listed in `groups`, the first one for a
+/// program in several of them.
+fn named_groups(yaml: &Yaml) -> HashMap<String, String> {
+    let mut named = HashMap::new();
+    for (group, members) in yaml["groups"].as_hash().into_iter().flatten() {
+        let (group, members) = match (group.as_str(), members.as_vec()) {
+            (Some(g), Some(m)) => (g, m),
+            _ => continue,
+        };
+        for member in members.iter().filter_map(Yaml::as_str) {
+            named.entry(member.to_string()).or_insert_with(|| group.to_string());
+        }
+    }
+    named
+}
+
+/// Create the configs of the `numprocs` instances of a program, named after
+/// its `process_name`.
+fn expand_program(program: &str, source: &Source, numprocs: i64) -> Result<Vec<ProgramConfig>, ConfigErrors> {
+    let yconf = &source.yaml;
+    if numprocs < 1 {
+        return Err(ConfigError::from_invalid_value("numprocs").into());
+    }
+    let start = get_num_field(yconf, "numprocs_start", DFLT_NUMPROCS_START)?;
+    let dflt_name = if numprocs == 1 { DFLT_PROCESS_NAME } else { DFLT_NUMBERED_PROCESS_NAME };
+    let process_name = get_str_field(yconf, "process_name", Some(dflt_name))?;
+    if numprocs > 1 && !process_name.contains("%(process_num)") {
+        return Err(ConfigError::from_process_name_without_num().into());
+    }
+    (start..start + numprocs)
+        .map(|process_num| {
+            let vars = ProcessVars {
+                program_name: program,
+                process_num,
+                group_name: &source.group,
+                here: &source.here,
+                num_placeholder: None,
+            };
+            let name = vars.interpolate(&process_name, "process_name")?;
+            let mut conf = ProgramConfig::from_yaml(yconf, name, &vars)?;
+            conf.group = program.to_string();
+            conf.group_name = source.group.clone();
+            conf.numprocs = numprocs;
+            conf.process_num = process_num;
+            Ok(conf)
+        })
+        .collect()
+}
+
+/// Replace the `old` instances of a program found in `names` by the `new` ones.
+fn replace_instances(names: &mut Vec<String>, old: &[String], new: &[String]) {
+    if let Some(i) = names.iter().position(|n| old.contains(n)) {
+        names.retain(|n| !old.contains(n));
+        names.splice(i..i, new.iter().cloned());
+    }
+}
+
+fn get_groups(yaml: &Yaml, field: &str, instances: HashMap<String, Vec<String>>) -> Result<HashMap<String, Vec<String>>, ConfigErrors> {
+    let ygroups = match &yaml[field] {
+        Yaml::BadValue => return Ok(instances),
+        Yaml::Hash(h) => h,
+        _ => return Err(ConfigError::from_not_hash(field).into()),
+    };
+    let mut groups = HashMap::new();
+    let mut errors = ConfigErrors::default();
+    for (yname, _) in ygroups {
+        let name = match yname.as_str() {
+            Some(n) => n,
+            None => {
+                errors.push(ConfigError::from_invalid_name("group"));
+                continue;
+            }
+        };
+        if instances.contains_key(name) {
+            errors.push(ConfigError::from_group_name_conflict(name));
+        }
+        let mut members = Vec::new();
+        let progs = get_str_vec_field(&yaml[field], name, Vec::new()).map_err(|e| e.within(field));
+        for prog in errors.check(progs, Vec::new()) {
+            match instances.get(&prog) {
+                Some(names) => members.extend(names.iter().cloned()),
+                None => errors.push(ConfigError::from_unknown_group_member(name, &prog)),
+            }
+        }
+        groups.insert(name.to_string(), members);
+    }
+    groups.extend(instances);
+    errors.into_result(groups)
+}
+
+/// Replace the program names in `depends_on` with the names of their
+/// instances.
+fn expand_dependencies(conf: &ProgramConfig, instances: &HashMap<String, Vec<String>>) -> Result<Vec<String>, ConfigError> {
+    let mut deps = Vec::new();
+    for dep in &conf.depends_on {
+        match instances.get(dep) {
+            Some(names) => deps.extend(names.iter().cloned()),
+            None => return Err(ConfigError::from_unknown_dependency(dep)),
+        }
+    }
+    Ok(deps)
+}
+
+impl FromStr for Config {
+    type Err = ConfigErrors;
+
+    fn from_str(str: &str) -> Result<Config, ConfigErrors> {
+        Config::from_str(str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ProgramConfig {
+    #[serde(skip)]
     pub name: String,
     pub cmd: String,
+    #[serde(skip)]
     pub numprocs: i64,
+    #[serde(serialize_with = "serialize_umask")]
     pub umask: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub workingdir: Option<String>,
     pub autostart: bool,
     pub autorestart: RestartPolicy,
@@ -80,30 +1016,141 @@ pub struct ProgramConfig {
     pub stderr: LogPath,
     pub startretries: i64,
     pub starttime: i64,
+    #[serde(serialize_with = "serialize_signal")]
     pub stopsignal: Signal,
     pub stoptime: i64,
     pub env: HashMap<String, String>,
+    #[serde(rename = "type")]
+    pub proc_type: ProcessType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pidfile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<HealthCheck>,
+    pub ready: ReadyPolicy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<i64>,
+    /// Instances of the programs that must be RUNNING before this one starts.
+    pub depends_on: Vec<String>,
+    pub priority: i64,
+    /// Name of the program this instance was expanded from.
+    #[serde(skip)]
+    pub group: String,
+    /// Named group the program belongs to, or the program itself.
+    #[serde(skip)]
+    pub group_name: String,
+    pub labels: HashMap<String, String>,
+    #[serde(skip)]
+    pub process_num: i64,
+    /// Start the program on this schedule, as a job allowed to exit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    pub overlap: OverlapPolicy,
 }
 
 impl ProgramConfig {
-    fn from_yaml(yaml: &Yaml, name: String) -> Result<ProgramConfig, ConfigError> {
-        Ok(ProgramConfig {
+    /// Read the config of an instance, reporting the problems of all its
+    /// fields.
+    fn from_yaml(yaml: &Yaml, name: String, vars: &ProcessVars) -> Result<ProgramConfig, ConfigErrors> {
+        let mut e = ConfigErrors::default();
+        let mut conf = ProgramConfig {
             name,
-            cmd: get_str_field(yaml, "cmd", None)?,
-            numprocs: get_num_field(yaml, "numprocs", DFLT_NUMPROCS)?,
-            umask: get_umask(yaml, "umask")?,
-            workingdir: get_opt_str_field(yaml, "workingdir", DFLT_CWD)?,
-            autostart: get_bool_field(yaml, "autostart", DFLT_AUTOSTART)?,
-            autorestart: get_autorestart(yaml, "autorestart")?,
-            exitcodes: get_num_vec_field(yaml, "exitcodes", DFLT_EXITCODES.to_vec())?,
-            startretries: get_num_field(yaml, "startretries", DFLT_STARTRETRIES)?,
-            starttime: get_num_field(yaml, "starttime", DFLT_STARTTIME)?,
-            stopsignal: get_signal_field(yaml, "stopsignal", DFLT_STOPSIGNAL)?,
-            stoptime: get_num_field(yaml, "stoptime", DFLT_STOPTIME)?,
-            stdout: get_log_path_field(yaml, "stdout", DFLT_STDOUT)?,
-            stderr: get_log_path_field(yaml, "stderr", DFLT_STDERR)?,
-            env: get_hash_str_field(yaml, "env", HashMap::new())?,
-        })
+            cmd: e.check(get_str_field(yaml, "cmd", None), String::new()),
+            numprocs: e.check(get_num_field(yaml, "numprocs", DFLT_NUMPROCS), DFLT_NUMPROCS),
+            umask: e.check(get_umask(yaml, "umask"), DFLT_UMASK),
+            workingdir: e.check(get_opt_str_field(yaml, "workingdir", DFLT_CWD), DFLT_CWD),
+            autostart: e.check(get_bool_field(yaml, "autostart", DFLT_AUTOSTART), DFLT_AUTOSTART),
+            autorestart: e.check(get_autorestart(yaml, "autorestart"), DFLT_AUTORESTART),
+            exitcodes: e.check(get_num_vec_field(yaml, "exitcodes", DFLT_EXITCODES.to_vec()), Vec::new()),
+            startretries: e.check(get_num_field(yaml, "startretries", DFLT_STARTRETRIES), DFLT_STARTRETRIES),
+            starttime: e.check(get_num_field(yaml, "starttime", DFLT_STARTTIME), DFLT_STARTTIME),
+            stopsignal: e.check(get_signal_field(yaml, "stopsignal", DFLT_STOPSIGNAL), Signal::SIGTERM),
+            stoptime: e.check(get_num_field(yaml, "stoptime", DFLT_STOPTIME), DFLT_STOPTIME),
+            stdout: e.check(get_log_path_field(yaml, "stdout", DFLT_STDOUT), LogPath::Auto),
+            stderr: e.check(get_log_path_field(yaml, "stderr", DFLT_STDERR), LogPath::Auto),
+            env: e.check(get_env(yaml, vars.here), HashMap::new()),
+            proc_type: e.check(get_proc_type(yaml, "type"), DFLT_TYPE),
+            pidfile: e.check(get_opt_str_field(yaml, "pidfile", None), None),
+            healthcheck: e.check(get_healthcheck(yaml, "healthcheck"), None),
+            ready: e.check(get_ready(yaml, "ready"), DFLT_READY),
+            watchdog: e.check(get_opt_num_field(yaml, "watchdog"), None),
+            depends_on: e.check(get_str_vec_field(yaml, "depends_on", Vec::new()), Vec::new()),
+            priority: e.check(get_num_field(yaml, "priority", DFLT_PRIORITY), DFLT_PRIORITY),
+            group: String::new(),
+            group_name: String::new(),
+            labels: e.check(get_hash_str_field(yaml, "labels", HashMap::new()), HashMap::new()),
+            process_num: 0,
+            schedule: e.check(get_schedule(yaml, "schedule"), None),
+            overlap: e.check(get_overlap(yaml, "overlap"), DFLT_OVERLAP),
+        };
+        e.check(conf.interpolate(vars), ());
+        if conf.proc_type == ProcessType::Forking && conf.pidfile.is_none() {
+            e.push(ConfigError::from_missing_pidfile());
+        }
+        if conf.watchdog.is_some() && conf.ready != ReadyPolicy::Notify {
+            e.push(ConfigError::from_watchdog_without_notify());
+        }
+        e.into_result(conf)
+    }
+
+    /// Check that the binary of `cmd` and `workingdir` exist.
+    fn check_paths(&self) -> Result<(), ConfigError> {
+        let bin = self.cmd.split_whitespace().next().unwrap_or_default();
+        let found = if bin.contains('/') {
+            Path::new(bin).is_file()
+        } else {
+            env::var("PATH")
+                .unwrap_or_default()
+                .split(':')
+                .any(|dir| Path::new(dir).join(bin).is_file())
+        };
+        if !found {
+            return Err(ConfigError::from_missing_path("cmd", bin));
+        }
+        match &self.workingdir {
+            Some(wd) if !Path::new(wd).is_dir() => Err(ConfigError::from_missing_path("workingdir", wd)),
+            _ => Ok(()),
+        }
+    }
+
+    fn interpolate(&mut self, vars: &ProcessVars) -> Result<(), ConfigError> {
+        self.cmd = vars.interpolate(&self.cmd, "cmd")?;
+        if let LogPath::Path(p) = &self.stdout {
+            self.stdout = LogPath::Path(vars.interpolate(p, "stdout")?);
+        }
+        if let LogPath::Path(p) = &self.stderr {
+            self.stderr = LogPath::Path(vars.interpolate(p, "stderr")?);
+        }
+        if let Some(wd) = &self.workingdir {
+            self.workingdir = Some(vars.interpolate(wd, "workingdir")?);
+        }
+        for v in self.env.values_mut() {
+            *v = vars.interpolate(v, "env")?;
+        }
+        Ok(())
+    }
+
+    /// Whether a change to a field, as returned by `diff`, only applies to a
+    /// running process once it is restarted.
+    pub fn requires_restart(field: &str) -> bool {
+        let field = field.split('.').next().unwrap_or_default();
+        RESTART_FIELDS.contains(&field)
+    }
+
+    /// Fields that differ from `other`, with the keys of maps as in
+    /// `env.ANSWER`.
+    pub fn diff(&self, other: &ProgramConfig) -> Vec<String> {
+        let mut fields = Vec::new();
+        diff_values(&json!(self), &json!(other), "", &mut fields);
+        // not serialized, as they come from the expansion of the program
+        let expansion = [
+            ("group", self.group != other.group),
+            ("group_name", self.group_name != other.group_name),
+            ("numprocs", self.numprocs != other.numprocs),
+            ("process_num", self.process_num != other.process_num),
+        ];
+        fields.extend(expansion.iter().filter(|(_, changed)| *changed).map(|(f, _)| f.to_string()));
+        fields.sort();
+        fields
     }
 
     pub fn open_stdout(&self) -> Stdio {
@@ -139,18 +1186,10 @@ impl ProgramConfig {
     }
 }
 
-fn gen_name(numprocs: i64, base_name: &str, i: i64) -> String {
-    if numprocs == 1 {
-        base_name.to_string()
-    } else {
-        base_name.to_string() + &i.to_string()
-    }
-}
-
 fn get_str_field(prog: &Yaml, field: &str, default: Option<&str>) -> Result<String, ConfigError> {
     match (&prog[field], default) {
         (Yaml::BadValue, Some(d)) => Ok(d.to_string()),
-        (Yaml::BadValue, None) => Err(ConfigError::new(&format!("missing value for field: {}", field))),
+        (Yaml::BadValue, None) => Err(ConfigError::from_missing_field(field)),
         (Yaml::String(s), _) => Ok(s.to_string()),
         (_, _) => Err(ConfigError::from_not_string(field)),
     }
@@ -164,11 +1203,25 @@ fn get_num_field(prog: &Yaml, field: &str, default: i64) -> Result<i64, ConfigEr
     }
 }
 
+fn get_opt_num_field(prog: &Yaml, field: &str) -> Result<Option<i64>, ConfigError> {
+    match prog[field] {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(n) => Ok(Some(n)),
+        _ => Err(ConfigError::from_not_number(field))
+    }
+}
+
 fn get_umask(prog: &Yaml, field: &str) -> Result<u32, ConfigError> {
     match prog[field] {
         Yaml::BadValue => Ok(DFLT_UMASK),
-        Yaml::Integer(n) => Ok(u32::from_str_radix(&n.to_string(), 8)
-                               .expect("field should be valid")),
+        Yaml::Integer(n) => match u32::from_str_radix(&n.to_string(), 8) {
+            Ok(umask) => Ok(umask),
+            Err(_) => Err(ConfigError::from_invalid_value(field)),
+        },
+        Yaml::String(ref s) => match u32::from_str_radix(s, 8) {
+            Ok(umask) => Ok(umask),
+            Err(_) => Err(ConfigError::from_not_number(field)),
+        },
         _ => Err(ConfigError::from_not_number(field)),
     }
 }
@@ -186,31 +1239,117 @@ fn get_autorestart(prog: &Yaml, field: &str) -> Result<RestartPolicy, ConfigErro
     Err(ConfigError::from_invalid_value(field))
 }
 
-fn get_opt_str_field(prog: &Yaml, field: &str, default: Option<String> ) -> Result<Option<String>, ConfigError> {
-    match (&prog[field], default) {
-        (Yaml::BadValue, Some(d)) => Ok(Some(d.to_string())),
-        (Yaml::BadValue, None) => Ok(None),
-        (Yaml::String(s), _) => Ok(Some(s.to_string())),
-        (_, _) => Err(ConfigError::from_not_string(field)),
+fn get_proc_type(prog: &Yaml, field: &str) -> Result<ProcessType, ConfigError> {
+    match &prog[field] {
+        Yaml::BadValue => Ok(DFLT_TYPE),
+        Yaml::String(s) => match ProcessType::from_str(s) {
+            Ok(t) => Ok(t),
+            Err(_) => Err(ConfigError::from_invalid_value(field)),
+        },
+        _ => Err(ConfigError::from_not_string(field)),
     }
 }
 
-fn get_bool_field(prog: &Yaml, field: &str, default: bool) -> Result<bool, ConfigError> {
-    match prog[field] {
-        Yaml::BadValue => Ok(default),
-        Yaml::Boolean(b) => Ok(b),
-        _ => Err(ConfigError::from_not_bool(field)),
+fn get_schedule(prog: &Yaml, field: &str) -> Result<Option<Schedule>, ConfigError> {
+    match &prog[field] {
+        Yaml::BadValue => Ok(None),
+        Yaml::String(s) => match Cron::new(s).parse() {
+            Ok(cron) => Ok(Some(Schedule { pattern: s.to_string(), cron })),
+            Err(e) => Err(ConfigError::from_invalid_schedule(field, e)),
+        },
+        _ => Err(ConfigError::from_not_string(field)),
     }
 }
 
-//TODO: handle single int value
-fn get_num_vec_field(prog: &Yaml, field: &str, default: Vec<i64>) -> Result<Vec<i64>, ConfigError> {
+fn get_overlap(prog: &Yaml, field: &str) -> Result<OverlapPolicy, ConfigError> {
+    match &prog[field] {
+        Yaml::BadValue => Ok(DFLT_OVERLAP),
+        Yaml::String(s) => match OverlapPolicy::from_str(s) {
+            Ok(o) => Ok(o),
+            Err(_) => Err(ConfigError::from_invalid_value(field)),
+        },
+        _ => Err(ConfigError::from_not_string(field)),
+    }
+}
+
+fn get_ready(prog: &Yaml, field: &str) -> Result<ReadyPolicy, ConfigError> {
+    match &prog[field] {
+        Yaml::BadValue => Ok(DFLT_READY),
+        Yaml::String(s) => match ReadyPolicy::from_str(s) {
+            Ok(r) => Ok(r),
+            Err(_) => Err(ConfigError::from_invalid_value(field)),
+        },
+        _ => Err(ConfigError::from_not_string(field)),
+    }
+}
+
+fn get_healthcheck(prog: &Yaml, field: &str) -> Result<Option<HealthCheck>, ConfigErrors> {
+    let hc = match &prog[field] {
+        Yaml::BadValue => return Ok(None),
+        Yaml::Hash(_) => &prog[field],
+        _ => return Err(ConfigError::from_not_hash(field).into()),
+    };
+    get_probe(hc).map_err(|e| ConfigErrors::from(e.within(field)))
+}
+
+fn get_probe(hc: &Yaml) -> Result<Option<HealthCheck>, ConfigError> {
+    let mut probes = Vec::new();
+    if let Some(cmd) = get_opt_str_field(hc, "cmd", None)? {
+        probes.push(Probe::Cmd(cmd));
+    }
+    match hc["tcp"] {
+        Yaml::BadValue => (),
+        Yaml::Integer(n) => match u16::try_from(n) {
+            Ok(port) => probes.push(Probe::Tcp(port)),
+            Err(_) => return Err(ConfigError::from_invalid_value("tcp")),
+        },
+        _ => return Err(ConfigError::from_not_number("tcp")),
+    }
+    if let Some(path) = get_opt_str_field(hc, "unix", None)? {
+        probes.push(Probe::Unix(path));
+    }
+    if let Some(url) = get_opt_str_field(hc, "http", None)? {
+        match Probe::from_http_url(&url) {
+            Some(p) => probes.push(p),
+            None => return Err(ConfigError::from_invalid_value("http")),
+        }
+    }
+    if probes.len() != 1 {
+        return Err(ConfigError::from_invalid_probe_count());
+    }
+    Ok(Some(HealthCheck {
+        probe: probes.remove(0),
+        interval: get_num_field(hc, "interval", DFLT_HEALTH_INTERVAL)?,
+        timeout: get_num_field(hc, "timeout", DFLT_HEALTH_TIMEOUT)?,
+        retries: get_num_field(hc, "retries", DFLT_HEALTH_RETRIES)?,
+    }))
+}
+
+fn get_opt_str_field(prog: &Yaml, field: &str, default: Option<String> ) -> Result<Option<String>, ConfigError> {
+    match (&prog[field], default) {
+        (Yaml::BadValue, Some(d)) => Ok(Some(d.to_string())),
+        (Yaml::BadValue, None) => Ok(None),
+        (Yaml::String(s), _) => Ok(Some(s.to_string())),
+        (_, _) => Err(ConfigError::from_not_string(field)),
+    }
+}
+
+fn get_bool_field(prog: &Yaml, field: &str, default: bool) -> Result<bool, ConfigError> {
+    match prog[field] {
+        Yaml::BadValue => Ok(default),
+        Yaml::Boolean(b) => Ok(b),
+        _ => Err(ConfigError::from_not_bool(field)),
+    }
+}
+
+//TODO: handle single int value
+fn get_num_vec_field(prog: &Yaml, field: &str, default: Vec<i64>) -> Result<Vec<i64>, ConfigError> {
     let f = match &prog[field] {
         Yaml::BadValue => return Ok(default),
         Yaml::Array(v) => Ok(v),
         _ => Err(ConfigError::from_not_array(field)),
     }?;
-    f.into_iter()
+    f.iter()
         .map(|n| match n.as_i64() {
             Some(n) => Ok(n),
             None => Err(ConfigError::from_array_value_not_nbr(field)),
@@ -218,6 +1357,21 @@ fn get_num_vec_field(prog: &Yaml, field: &str, default: Vec<i64>) -> Result<Vec<
     .collect()
 }
 
+fn get_str_vec_field(prog: &Yaml, field: &str, default: Vec<String>) -> Result<Vec<String>, ConfigError> {
+    let f = match &prog[field] {
+        Yaml::BadValue => return Ok(default),
+        Yaml::String(s) => return Ok(vec![s.to_string()]),
+        Yaml::Array(v) => Ok(v),
+        _ => Err(ConfigError::from_not_array(field)),
+    }?;
+    f.iter()
+        .map(|s| match s.as_str() {
+            Some(s) => Ok(s.to_string()),
+            None => Err(ConfigError::from_array_value_not_string(field)),
+        })
+    .collect()
+}
+
 fn get_signal_field(prog: &Yaml, field: &str, default: &str) -> Result<Signal, ConfigError> {
     let ss = get_str_field(prog, field, Some(default))?;
     match ("SIG".to_owned() + &ss).parse::<Signal>() {
@@ -238,6 +1392,53 @@ fn get_log_path_field(prog: &Yaml, field: &str, default: &str) -> Result<LogPath
     }
 }
 
+/// Variables of the `env_file` dotenv files of a program, in order, then of
+/// its `env`.
+fn get_env(prog: &Yaml, here: &str) -> Result<HashMap<String, String>, ConfigErrors> {
+    let mut errors = ConfigErrors::default();
+    let mut env = HashMap::new();
+    for file in errors.check(get_str_vec_field(prog, "env_file", Vec::new()), Vec::new()) {
+        match fs::read_to_string(Path::new(here).join(&file)) {
+            Ok(s) => env.extend(errors.check(parse_dotenv(&s, &file), Vec::new())),
+            Err(e) => errors.push(ConfigError::from_unreadable_env_file(&file, e)),
+        }
+    }
+    env.extend(errors.check(get_hash_str_field(prog, "env", HashMap::new()), HashMap::new()));
+    errors.into_result(env)
+}
+
+/// Parse the `KEY=value` lines of a dotenv file, which may start with
+/// `export` and have quoted values.
+fn parse_dotenv(str: &str, file: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut vars = Vec::new();
+    for (i, line) in str.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() && k.trim().chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                (k.trim(), v.trim())
+            }
+            _ => return Err(ConfigError::from_invalid_env_file(file, i + 1)),
+        };
+        let value = match value.chars().next() {
+            Some(q @ ('"' | '\'')) if value.len() > 1 && value.ends_with(q) => {
+                let inner = &value[1..value.len() - 1];
+                if q == '"' {
+                    inner.replace("\\n", "\n").replace("\\\"", "\"")
+                } else {
+                    inner.to_string()
+                }
+            }
+            _ => value.split(" #").next().unwrap_or_default().trim_end().to_string(),
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
 fn get_hash_str_field(prog: &Yaml, field: &str, default: HashMap<String, String>) -> Result<HashMap<String, String>, ConfigError> {
     let f = match &prog[field] {
         Yaml::BadValue => return Ok(default),
@@ -248,26 +1449,21 @@ fn get_hash_str_field(prog: &Yaml, field: &str, default: HashMap<String, String>
         .map(|(k, v)| {
             let new_k = match k.into_string() {
                 Some(k) => k,
-                None => {
-                    return Err(ConfigError::new(&format!(
-                                "invalid key in field: {}",
-                                field
-                                )))
-                }
+                None => return Err(ConfigError::from_invalid_hash_key(field)),
             };
-            //TODO: handle other types and convert them to string
-            let new_v = match v.into_string() {
-                Some(v) => v,
-                None => {
-                    return Err(ConfigError::from_hash_value_not_string(field))
-                }
+            let new_v = match v {
+                Yaml::String(s) | Yaml::Real(s) => s,
+                Yaml::Integer(n) => n.to_string(),
+                Yaml::Boolean(b) => b.to_string(),
+                _ => return Err(ConfigError::from_hash_value_not_string(field)),
             };
             Ok((new_k, new_v))
         })
     .collect()
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RestartPolicy {
     Always,
     Never,
@@ -288,6 +1484,163 @@ impl FromStr for RestartPolicy {
     }
 }
 
+/// How the daemon tracks the process of a program.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessType {
+    /// The spawned command is the process to supervise.
+    Simple,
+    /// The spawned command forks into the background and writes the pid of
+    /// the process to supervise to `pidfile`.
+    Forking,
+}
+
+impl FromStr for ProcessType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ProcessType, Self::Err> {
+        match s {
+            "simple" => Ok(ProcessType::Simple),
+            "forking" => Ok(ProcessType::Forking),
+            _ => Err(()),
+        }
+    }
+}
+
+/// When a STARTING program is considered RUNNING.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadyPolicy {
+    /// Once it stayed up for `starttime` seconds.
+    Starttime,
+    /// Once it sent `READY=1` on its `NOTIFY_SOCKET`.
+    Notify,
+}
+
+impl FromStr for ReadyPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ReadyPolicy, Self::Err> {
+        match s {
+            "starttime" => Ok(ReadyPolicy::Starttime),
+            "notify" => Ok(ReadyPolicy::Notify),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Cron expression of a scheduled program, as in `*/5 * * * *`.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pattern: String,
+    cron: Cron,
+}
+
+impl Schedule {
+    /// First time the schedule matches after `time`.
+    pub fn next_after(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron.find_next_occurrence(time, false).ok()
+    }
+}
+
+impl PartialEq for Schedule {
+    fn eq(&self, other: &Schedule) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+/// What to do when a scheduled program is due while its previous run is
+/// still going.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    /// Drop the run.
+    Skip,
+    /// Start the run once the previous one exited.
+    Queue,
+}
+
+impl FromStr for OverlapPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<OverlapPolicy, Self::Err> {
+        match s {
+            "skip" => Ok(OverlapPolicy::Skip),
+            "queue" => Ok(OverlapPolicy::Queue),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Periodic probe run against a RUNNING program.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub probe: Probe,
+    pub interval: i64,
+    pub timeout: i64,
+    /// Number of consecutive failures before the program is unhealthy.
+    pub retries: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Probe {
+    /// Command that should exit with 0.
+    Cmd(String),
+    /// Port on localhost that should accept connections.
+    Tcp(u16),
+    /// Unix socket that should accept connections.
+    Unix(String),
+    /// URL on localhost that should answer with a 2xx or 3xx status.
+    Http { host: String, port: u16, path: String },
+}
+
+impl Probe {
+    fn from_http_url(url: &str) -> Option<Probe> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        // an IPv6 host is bracketed, as in `[::1]:8080`
+        let end = authority.find(']').unwrap_or(0);
+        let (host, port) = match authority[end..].rsplit_once(':') {
+            Some((_, p)) => (&authority[..authority.len() - p.len() - 1], p.parse::<u16>().ok()?),
+            None => (authority, 80),
+        };
+        match host {
+            "localhost" | "127.0.0.1" | "[::1]" => Some(Probe::Http {
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Written back as the field it is read from, e.g. `http: <url>`.
+impl Serialize for Probe {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            Probe::Cmd(cmd) => map.serialize_entry("cmd", cmd)?,
+            Probe::Tcp(port) => map.serialize_entry("tcp", port)?,
+            Probe::Unix(path) => map.serialize_entry("unix", path)?,
+            Probe::Http { host, port, path } => {
+                map.serialize_entry("http", &format!("http://{}:{}{}", host, port, path))?
+            }
+        }
+        map.end()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum LogPath {
     Path(String),
@@ -306,81 +1659,430 @@ impl LogPath {
     }
 }
 
-#[derive(Debug)]
+impl Serialize for LogPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            LogPath::Path(p) => serializer.serialize_str(p),
+            LogPath::Auto => serializer.serialize_str("AUTO"),
+            LogPath::Non => serializer.serialize_str("NONE"),
+        }
+    }
+}
+
+/// Written with the octal digits it is read as, `0o22` giving `22`.
+fn serialize_umask<S: Serializer>(umask: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:03o}", umask))
+}
+
+fn serialize_signal<S: Serializer>(signal: &Signal, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(signal.as_str().trim_start_matches("SIG"))
+}
+
+/// Output format of a resolved config.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpFormat {
+    Yaml,
+    Json,
+}
+
+impl FromStr for DumpFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<DumpFormat, Self::Err> {
+        match s {
+            "yaml" => Ok(DumpFormat::Yaml),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+fn diff_values(a: &Value, b: &Value, path: &str, fields: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_values(a.get(key).unwrap_or(&Value::Null), b.get(key).unwrap_or(&Value::Null), &path, fields);
+            }
+        }
+        _ if a != b => fields.push(path.to_string()),
+        _ => (),
+    }
+}
+
+/// Escape `${` as `$${` in the strings of a written config, so reading it
+/// back does not expand them again.
+fn escape_env_refs(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = s.replace("${", "$${"),
+        Value::Array(a) => a.iter_mut().for_each(escape_env_refs),
+        Value::Object(o) => o.values_mut().for_each(escape_env_refs),
+        _ => (),
+    }
+}
+
+fn escape_yaml_env_refs(yaml: &Yaml) -> Yaml {
+    match yaml {
+        Yaml::String(s) => Yaml::String(s.replace("${", "$${")),
+        Yaml::Array(a) => Yaml::Array(a.iter().map(escape_yaml_env_refs).collect()),
+        Yaml::Hash(h) => Yaml::Hash(h.iter().map(|(k, v)| (k.clone(), escape_yaml_env_refs(v))).collect()),
+        y => y.clone(),
+    }
+}
+
+fn json_to_yaml(value: &Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Array(a) => Yaml::Array(a.iter().map(json_to_yaml).collect()),
+        Value::Object(o) => Yaml::Hash(
+            o.iter()
+                .map(|(k, v)| (Yaml::String(k.clone()), json_to_yaml(v)))
+                .collect(),
+        ),
+    }
+}
+
+/// Category of a config problem.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// A file could not be read.
+    Io,
+    /// A file is not valid YAML.
+    Syntax,
+    /// A required field is missing.
+    MissingField,
+    /// A field does not have the expected type.
+    WrongType,
+    /// A field has a value that is not accepted.
+    InvalidValue,
+    /// A key is not a known field.
+    UnknownKey,
+    /// A program, template or group that does not exist is referred to.
+    UnknownName,
+    /// Names clash or references form a cycle.
+    Conflict,
+}
+
+/// A problem found in a config, located as precisely as possible.
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConfigError {
-    details: String,
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Program the problem was found in.
+    pub program: Option<String>,
+    /// Dotted path of the field, relative to the program if any.
+    pub field: Option<String>,
+    pub file: Option<String>,
+    /// Line and column, from 1.
+    pub position: Option<(u32, u32)>,
 }
 
 impl ConfigError {
-    fn new(msg: &str) -> ConfigError {
+    fn new(kind: ErrorKind, msg: &str) -> ConfigError {
         ConfigError {
-            details: msg.to_string(),
+            kind,
+            message: msg.to_string(),
+            program: None,
+            field: None,
+            file: None,
+            position: None,
         }
     }
 
+    fn at(mut self, field: &str) -> ConfigError {
+        self.field = Some(field.to_string());
+        self
+    }
+
+    /// Nest the field under `section`.
+    fn within(mut self, section: &str) -> ConfigError {
+        if !section.is_empty() {
+            self.field = Some(match self.field {
+                Some(f) => format!("{}.{}", section, f),
+                None => section.to_string(),
+            });
+        }
+        self
+    }
+
+    fn in_program(mut self, program: &str) -> ConfigError {
+        self.program.get_or_insert_with(|| program.to_string());
+        self
+    }
+
+    fn in_file(mut self, file: &str) -> ConfigError {
+        self.file.get_or_insert_with(|| file.to_string());
+        self
+    }
+
+    /// Path of the YAML key the problem is about.
+    fn key_path(&self) -> Vec<String> {
+        let mut path = Vec::new();
+        if let Some(p) = &self.program {
+            path.extend(["programs".to_string(), p.clone()]);
+        }
+        if let Some(f) = &self.field {
+            path.extend(f.split('.').map(String::from));
+        }
+        path
+    }
+
     fn from_unreadable_file(e: std::io::Error) -> ConfigError {
-        ConfigError::new(&format!("failed to read config file: {}", e))
+        ConfigError::new(ErrorKind::Io, &format!("failed to read config file: {}", e))
+    }
+
+    fn from_invalid_include(pattern: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::InvalidValue, &format!("invalid include pattern: {}", pattern)).at("include")
+    }
+
+    fn from_duplicate_program(first: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::Conflict, &format!("already defined in {}", first))
     }
 
-    fn from_invalid_cfg_file(e: ConfigError) -> ConfigError {
-        ConfigError::new(&format!("invalid config file: {}", e))
+    fn from_missing_field(field: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::MissingField, &format!("missing value for field: {}", field)).at(field)
+    }
+
+    fn from_invalid_name(what: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::WrongType, &format!("{} name is not a string", what))
     }
 
     fn from_invalid_value(field: &str) -> ConfigError {
-        ConfigError::new(&format!("invalid value for field: {}", field))
+        ConfigError::new(ErrorKind::InvalidValue, &format!("invalid value for field: {}", field)).at(field)
     }
 
     fn from_not_string(field: &str) -> ConfigError {
-        ConfigError::new(&format!("field `{}` should be a string", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("field `{}` should be a string", field)).at(field)
     }
 
     fn from_not_number(field: &str) -> ConfigError {
-        ConfigError::new(&format!("field `{}` should be a number", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("field `{}` should be a number", field)).at(field)
     }
 
     fn from_not_bool(field: &str) -> ConfigError {
-        ConfigError::new(&format!("field `{}` should be a number", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("field `{}` should be a boolean", field)).at(field)
     }
 
     fn from_not_array(field: &str) -> ConfigError {
-        ConfigError::new(&format!("field `{}` should be an array", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("field `{}` should be an array", field)).at(field)
     }
 
     fn from_not_hash(field: &str) -> ConfigError {
-        ConfigError::new(&format!("field `{}` should be a hashmap", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("field `{}` should be a hashmap", field)).at(field)
+    }
+
+    fn from_missing_pidfile() -> ConfigError {
+        ConfigError::new(ErrorKind::MissingField, "field `pidfile` is required for forking programs").at("type")
+    }
+
+    fn from_watchdog_without_notify() -> ConfigError {
+        ConfigError::new(ErrorKind::InvalidValue, "field `watchdog` requires `ready: notify`").at("watchdog")
+    }
+
+    fn from_invalid_probe_count() -> ConfigError {
+        ConfigError::new(
+            ErrorKind::InvalidValue,
+            "field `healthcheck` needs exactly one of `cmd`, `tcp`, `unix` or `http`",
+        )
     }
 
     fn from_array_value_not_nbr(field: &str) -> ConfigError {
-        ConfigError::new(&format!("array `{}` values should be numbers", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("array `{}` values should be numbers", field)).at(field)
+    }
+
+    fn from_array_value_not_string(field: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::WrongType, &format!("array `{}` values should be strings", field)).at(field)
+    }
+
+    fn from_invalid_schedule(field: &str, e: croner::errors::CronError) -> ConfigError {
+        ConfigError::new(ErrorKind::InvalidValue, &format!("field `{}` is not a valid cron expression: {}", field, e)).at(field)
+    }
+
+    fn from_padded_placeholder(field: &str, placeholder: &str) -> ConfigError {
+        let msg = format!("field `{}` has a padded `{}`, which a template cannot pad", field, placeholder);
+        ConfigError::new(ErrorKind::InvalidValue, &msg).at(field)
+    }
+
+    fn from_invalid_interpolation(field: &str, placeholder: &str) -> ConfigError {
+        let msg = format!("field `{}` has an invalid placeholder `{}`", field, placeholder);
+        ConfigError::new(ErrorKind::InvalidValue, &msg).at(field)
+    }
+
+    fn from_process_name_without_num() -> ConfigError {
+        let msg = "field `process_name` needs `%(process_num)d` with several processes";
+        ConfigError::new(ErrorKind::InvalidValue, msg).at("process_name")
+    }
+
+    fn from_unknown_program(name: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::UnknownName, &format!("no such program: {}", name))
+    }
+
+    fn from_unknown_key(key: &str, suggestion: Option<&'static str>) -> ConfigError {
+        let hint = match suggestion {
+            Some(s) => format!(" (did you mean `{}`?)", s),
+            None => String::new(),
+        };
+        ConfigError::new(ErrorKind::UnknownKey, &format!("unknown key `{}`{}", key, hint)).at(key)
+    }
+
+    fn from_name_collision(name: &str, other: &str) -> ConfigError {
+        let msg = format!("process `{}` collides with program `{}`", name, other);
+        ConfigError::new(ErrorKind::Conflict, &msg).at("process_name")
+    }
+
+    fn from_missing_path(field: &str, path: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::InvalidValue, &format!("`{}` path {} does not exist", field, path)).at(field)
+    }
+
+    fn from_unknown_template(name: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::UnknownName, &format!("no such template: {}", name)).at("extends")
+    }
+
+    fn from_template_cycle(chain: &[String]) -> ConfigError {
+        ConfigError::new(ErrorKind::Conflict, &format!("template cycle: {}", chain.join(" -> "))).at("extends")
+    }
+
+    fn from_unknown_dependency(dep: &str) -> ConfigError {
+        let msg = format!("depends on unknown program `{}`", dep);
+        ConfigError::new(ErrorKind::UnknownName, &msg).at("depends_on")
+    }
+
+    fn from_group_name_conflict(name: &str) -> ConfigError {
+        let msg = format!("group `{}` has the same name as a program", name);
+        ConfigError::new(ErrorKind::Conflict, &msg).at(&format!("groups.{}", name))
+    }
+
+    fn from_unknown_group_member(name: &str, prog: &str) -> ConfigError {
+        let msg = format!("group `{}` contains unknown program `{}`", name, prog);
+        ConfigError::new(ErrorKind::UnknownName, &msg).at(&format!("groups.{}", name))
+    }
+
+    fn from_dependency_cycle(cycle: &[String]) -> ConfigError {
+        ConfigError::new(ErrorKind::Conflict, &format!("dependency cycle: {}", cycle.join(" -> ")))
     }
 
     fn from_hash_value_not_string(field: &str) -> ConfigError {
-        ConfigError::new(&format!("hashmap `{}` values should be strings", field))
+        ConfigError::new(ErrorKind::WrongType, &format!("hashmap `{}` values should be strings, numbers or booleans", field)).at(field)
+    }
+
+    fn from_undefined_variable(var: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::UnknownName, &format!("environment variable `{}` is not set", var))
     }
 
-    fn from_invalid_yaml(e: yaml_rust::ScanError) -> ConfigError {
-        ConfigError::new(&format!( "error scanning config file: {}", e.to_string()))
+    fn from_unreadable_env_file(file: &str, e: std::io::Error) -> ConfigError {
+        ConfigError::new(ErrorKind::Io, &format!("failed to read env file {}: {}", file, e)).at("env_file")
+    }
+
+    fn from_invalid_env_file(file: &str, line: usize) -> ConfigError {
+        ConfigError::new(ErrorKind::InvalidValue, &format!("env file {} line {}: expected KEY=value", file, line)).at("env_file")
+    }
+
+    fn from_invalid_hash_key(field: &str) -> ConfigError {
+        ConfigError::new(ErrorKind::WrongType, &format!("invalid key in field: {}", field)).at(field)
+    }
+
+    fn from_invalid_syntax(format: ConfigFormat, msg: &str, position: Option<(u32, u32)>) -> ConfigError {
+        // the position is reported on its own
+        let msg = msg.split(" at line ").next().unwrap_or_default();
+        let mut error = ConfigError::new(ErrorKind::Syntax, &format!("invalid {}: {}", format, msg));
+        error.position = position;
+        error
     }
 }
 
 impl fmt::Display for ConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.details)
+        if let Some(file) = &self.file {
+            match self.position {
+                Some((line, col)) => write!(f, "{}:{}:{}: ", file, line, col)?,
+                None => write!(f, "{}: ", file)?,
+            }
+        }
+        if let Some(program) = &self.program {
+            write!(f, "program `{}`: ", program)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Every problem found while loading a config.
+#[derive(Debug, Default)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl ConfigErrors {
+    fn push(&mut self, e: ConfigError) {
+        // instances of a program share their problems
+        if !self.0.contains(&e) {
+            self.0.push(e);
+        }
+    }
+
+    fn extend(&mut self, errors: ConfigErrors) {
+        for e in errors.0 {
+            self.push(e);
+        }
+    }
+
+    /// Value of a result, or `fallback` once its problems are recorded so
+    /// that loading goes on.
+    fn check<T, E: Into<ConfigErrors>>(&mut self, result: Result<T, E>, fallback: T) -> T {
+        match result {
+            Ok(v) => v,
+            Err(e) => {
+                self.extend(e.into());
+                fallback
+            }
+        }
+    }
+
+    fn into_result<T>(self, value: T) -> Result<T, ConfigErrors> {
+        if self.0.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+
+    fn in_program(self, program: &str) -> ConfigErrors {
+        ConfigErrors(self.0.into_iter().map(|e| e.in_program(program)).collect())
     }
 }
 
-impl Error for ConfigError {
-    fn description(&self) -> &str {
-        &self.details
+impl From<ConfigError> for ConfigErrors {
+    fn from(e: ConfigError) -> ConfigErrors {
+        ConfigErrors(vec![e])
     }
 }
 
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Error for ConfigErrors {}
+
 #[cfg(test)]
 mod tests {
-    use crate::cfg::{self, Config, RestartPolicy};
+    use crate::cfg::{self, Config, ConfigFormat, OverlapPolicy, ProgramConfig, RestartPolicy};
+    use chrono::{Local, TimeZone};
     use std::collections::HashMap;
+    use std::fs;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn without_default_values() {
         let yaml = "
 programs:
@@ -446,6 +2148,662 @@ programs:
         assert_eq!(c.programs["cat"].stdout, cfg::LogPath::Auto);
         assert_eq!(c.programs["cat"].stderr, cfg::LogPath::Auto);
         assert_eq!(c.programs["cat"].env, HashMap::new());
+        assert_eq!(c.programs["cat"].proc_type, cfg::DFLT_TYPE);
+        assert_eq!(c.programs["cat"].pidfile, None);
+        assert_eq!(c.programs["cat"].ready, cfg::DFLT_READY);
+        assert_eq!(c.programs["cat"].watchdog, None);
+        assert_eq!(c.programs["cat"].depends_on, Vec::<String>::new());
+        assert_eq!(c.programs["cat"].priority, cfg::DFLT_PRIORITY);
+        assert_eq!(c.programs["cat"].labels, HashMap::new());
+    }
+
+    #[test]
+    fn with_forking_type() {
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+    type: forking
+    pidfile: \"/run/nginx.pid\"";
+        let c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.programs["nginx"].proc_type, cfg::ProcessType::Forking);
+        assert_eq!(c.programs["nginx"].pidfile, Some("/run/nginx.pid".to_string()));
+    }
+
+    #[test]
+    fn with_forking_type_without_pidfile() {
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+    type: forking";
+        let c = Config::from_str(yaml);
+        assert!(c.is_err())
+    }
+
+    #[test]
+    fn with_healthcheck() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    healthcheck:
+      http: \"http://localhost:8080/health\"
+      interval: 5
+      timeout: 2
+      retries: 4";
+        let c = Config::from_str(yaml).unwrap();
+        let hc = c.programs["web"].healthcheck.clone().unwrap();
+        assert_eq!(hc.probe, cfg::Probe::Http {
+            host: "localhost".to_string(),
+            port: 8080,
+            path: "/health".to_string(),
+        });
+        assert_eq!(hc.interval, 5);
+        assert_eq!(hc.timeout, 2);
+        assert_eq!(hc.retries, 4);
+        for (url, port) in [("http://[::1]/health", 80), ("http://[::1]:8080/health", 8080)] {
+            let c = Config::from_str(&format!("
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    healthcheck:
+      http: \"{}\"", url)).unwrap();
+            assert_eq!(c.programs["web"].healthcheck.clone().unwrap().probe, cfg::Probe::Http {
+                host: "[::1]".to_string(),
+                port,
+                path: "/health".to_string(),
+            });
+        }
+    }
+
+    #[test]
+    fn with_healthcheck_default_values() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    healthcheck:
+      tcp: 8080";
+        let c = Config::from_str(yaml).unwrap();
+        let hc = c.programs["web"].healthcheck.clone().unwrap();
+        assert_eq!(hc.probe, cfg::Probe::Tcp(8080));
+        assert_eq!(hc.interval, cfg::DFLT_HEALTH_INTERVAL);
+        assert_eq!(hc.timeout, cfg::DFLT_HEALTH_TIMEOUT);
+        assert_eq!(hc.retries, cfg::DFLT_HEALTH_RETRIES);
+    }
+
+    #[test]
+    fn with_invalid_healthcheck() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    healthcheck:
+      tcp: 8080
+      unix: \"/tmp/web.sock\"";
+        assert!(Config::from_str(yaml).is_err());
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    healthcheck:
+      http: \"http://example.com/health\"";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn with_notify_ready() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    ready: notify
+    watchdog: 30";
+        let c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.programs["web"].ready, cfg::ReadyPolicy::Notify);
+        assert_eq!(c.programs["web"].watchdog, Some(30));
+    }
+
+    #[test]
+    fn with_watchdog_without_notify() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    watchdog: 30";
+        let c = Config::from_str(yaml);
+        assert!(c.is_err())
+    }
+
+    #[test]
+    fn with_dependencies() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    depends_on: [db, cache]
+  db:
+    cmd: \"/usr/bin/db\"
+    numprocs: 2
+    priority: 10
+  cache:
+    cmd: \"/usr/bin/cache\"
+    priority: 5
+  cron:
+    cmd: \"/usr/bin/cron\"
+    priority: 1";
+        let c = Config::from_str(yaml).unwrap();
+        assert_eq!(c.programs["web"].depends_on, vec!["db0", "db1", "cache"]);
+        assert_eq!(c.start_order(), vec!["cron", "cache", "db0", "db1", "web"]);
+    }
+
+    #[test]
+    fn with_unknown_dependency() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    depends_on: db";
+        let c = Config::from_str(yaml);
+        assert!(c.is_err())
+    }
+
+    #[test]
+    fn with_dependency_cycle() {
+        let yaml = "
+programs:
+  a:
+    cmd: \"/bin/a\"
+    depends_on: [b]
+  b:
+    cmd: \"/bin/b\"
+    depends_on: [c]
+  c:
+    cmd: \"/bin/c\"
+    depends_on: [a]";
+        let e = Config::from_str(yaml).unwrap_err();
+        assert_eq!(e.0[0].message, "dependency cycle: a -> b -> c -> a");
+    }
+
+    #[test]
+    fn with_located_errors() {
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    umask: nope
+    autostart: 1
+  ls:
+    cmd: \"/bin/ls\"
+    autorestat: never
+strict: true";
+        let e = Config::from_str(yaml).unwrap_err();
+        assert_eq!(e.0.len(), 3);
+        assert_eq!(e.0[0].kind, cfg::ErrorKind::WrongType);
+        assert_eq!(e.0[0].program.as_deref(), Some("cat"));
+        assert_eq!(e.0[0].field.as_deref(), Some("umask"));
+        assert_eq!(e.0[0].position, Some((5, 5)));
+        assert_eq!(e.0[1].to_string(), "<string>:6:5: program `cat`: field `autostart` should be a boolean");
+        assert_eq!(e.0[2].kind, cfg::ErrorKind::UnknownKey);
+        assert_eq!(e.0[2].position, Some((9, 5)));
+        let e = Config::from_str("programs: [").unwrap_err();
+        assert_eq!(e.0[0].kind, cfg::ErrorKind::Syntax);
+        assert!(e.0[0].position.is_some());
+    }
+
+    #[test]
+    fn with_includes() {
+        let dir = std::env::temp_dir().join(format!("taskmaster-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let main = dir.join("taskmaster.yaml");
+        fs::write(&main, "
+include: \"conf.d/*.yaml\"
+programs:
+  web:
+    cmd: \"/usr/bin/web\"").unwrap();
+        fs::write(dir.join("conf.d/db.yaml"), "
+programs:
+  db:
+    cmd: \"/usr/bin/db\"
+    workingdir: \"%(here)s\"").unwrap();
+        let c = Config::from_file(main.to_str().unwrap()).unwrap();
+        assert!(c.programs.contains_key("web"));
+        let conf_d = fs::canonicalize(dir.join("conf.d")).unwrap();
+        assert_eq!(c.programs["db"].workingdir.as_deref(), conf_d.to_str());
+        assert_eq!(c.files.len(), 2);
+        fs::write(dir.join("conf.d/web.yaml"), "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"").unwrap();
+        let e = Config::from_file(main.to_str().unwrap()).unwrap_err().to_string();
+        assert!(e.contains("taskmaster.yaml") && e.contains("web.yaml"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn with_other_formats() {
+        let dir = std::env::temp_dir().join(format!("taskmaster-formats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let yaml = write("ok.yaml", "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    numprocs: 2
+    umask: 022
+    env:
+      LANG: C");
+        let toml = write("ok.toml", "
+[programs.cat]
+cmd = \"/bin/cat\"
+numprocs = 2
+umask = 0o022
+env = { LANG = \"C\" }");
+        let json = write("ok.json", r#"{"programs": {"cat": {"cmd": "/bin/cat", "numprocs": 2, "umask": 18, "env": {"LANG": "C"}}}}"#);
+        let c = Config::from_file(&yaml).unwrap();
+        assert_eq!(c.programs["cat0"].umask, 0o22);
+        assert_eq!(Config::from_file(&toml).unwrap().programs, c.programs);
+        assert_eq!(Config::from_file(&json).unwrap().programs, c.programs);
+        let toml = write("bad.toml", "[programs.cat]\ncmd = \"/bin/cat\"\nautostart = \"yes\"");
+        let json = write("bad.json", r#"{"programs": {"cat": {"cmd": "/bin/cat", "autostart": "yes"}}}"#);
+        let toml = Config::from_file(&toml).unwrap_err();
+        let json = Config::from_file(&json).unwrap_err();
+        assert_eq!(toml.0[0].message, json.0[0].message);
+        assert_eq!(toml.0[0].field, json.0[0].field);
+        let e = Config::from_file_as(&write("bad.conf", "[programs"), ConfigFormat::Toml).unwrap_err();
+        assert!(e.0[0].message.starts_with("invalid TOML"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn with_env_expansion() {
+        std::env::set_var("TASKMASTER_TEST_BIN", "/bin/cat");
+        std::env::set_var("TASKMASTER_TEST_PROCS", "2");
+        let yaml = "
+programs:
+  cat:
+    cmd: \"${TASKMASTER_TEST_BIN} --price $$5 $${HOME}\"
+    numprocs: ${TASKMASTER_TEST_PROCS}
+    env:
+      PROCS: ${TASKMASTER_TEST_PROCS}
+      MODE: ${TASKMASTER_TEST_UNSET:-prod}
+      ANSWER: 42
+      DEBUG: false";
+        let c = Config::from_str(yaml).unwrap();
+        let p = &c.programs["cat0"];
+        assert_eq!(p.cmd, "/bin/cat --price $$5 ${HOME}");
+        assert_eq!(p.numprocs, 2);
+        assert_eq!(p.env["PROCS"], "2");
+        assert_eq!(p.env["MODE"], "prod");
+        assert_eq!(p.env["ANSWER"], "42");
+        assert_eq!(p.env["DEBUG"], "false");
+        // the escape is kept when the config is written back
+        let dumped = Config::from_str(&c.dump(cfg::DumpFormat::Yaml)).unwrap();
+        assert_eq!(dumped.programs["cat0"].cmd, p.cmd);
+        let effective = Config::from_str(&c.effective(&[]).unwrap()).unwrap();
+        assert_eq!(effective.programs["cat0"].cmd, p.cmd);
+        std::env::set_var("TASKMASTER_TEST_CMD", "true");
+        let c = Config::from_str("
+programs:
+  sh:
+    cmd: \"${TASKMASTER_TEST_CMD}\"
+    autostart: ${TASKMASTER_TEST_CMD}
+  pid:
+    cmd: \"sh -c 'echo $$'\"").unwrap();
+        assert_eq!(c.programs["sh"].cmd, "true");
+        assert!(c.programs["sh"].autostart);
+        assert_eq!(c.programs["pid"].cmd, "sh -c 'echo $$'");
+        let e = Config::from_str("
+programs:
+  cat:
+    cmd: \"/bin/cat ${TASKMASTER_TEST_UNSET}\"").unwrap_err();
+        assert_eq!(e.0[0].message, "environment variable `TASKMASTER_TEST_UNSET` is not set");
+        assert_eq!(e.0[0].program.as_deref(), Some("cat"));
+        assert_eq!(e.0[0].position, Some((4, 5)));
+    }
+
+    #[test]
+    fn with_env_file() {
+        let dir = std::env::temp_dir().join(format!("taskmaster-env-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.env"), "# secrets\nexport TOKEN=\"a b\"\nMODE=dev # local\n").unwrap();
+        let main = dir.join("taskmaster.yaml");
+        fs::write(&main, "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    env_file: app.env
+    env:
+      MODE: prod").unwrap();
+        let c = Config::from_file(main.to_str().unwrap()).unwrap();
+        assert_eq!(c.programs["cat"].env["TOKEN"], "a b");
+        assert_eq!(c.programs["cat"].env["MODE"], "prod");
+        fs::write(dir.join("app.env"), "TOKEN\n").unwrap();
+        let e = Config::from_file(main.to_str().unwrap()).unwrap_err();
+        assert_eq!(e.0[0].field.as_deref(), Some("env_file"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn program_diff() {
+        let old = Config::from_str("
+programs:
+  web:
+    cmd: \"/bin/web\"
+    env:
+      ANSWER: 42
+      LANG: C").unwrap();
+        let new = Config::from_str("
+programs:
+  web:
+    cmd: \"/bin/web --verbose\"
+    stoptime: 30
+    env:
+      ANSWER: 43
+      LANG: C").unwrap();
+        let fields = old.programs["web"].diff(&new.programs["web"]);
+        assert_eq!(fields, vec!["cmd", "env.ANSWER", "stoptime"]);
+        assert!(old.programs["web"].diff(&old.programs["web"]).is_empty());
+    }
+
+    #[test]
+    fn requires_restart() {
+        assert!(ProgramConfig::requires_restart("env.ANSWER"));
+        assert!(ProgramConfig::requires_restart("cmd"));
+        assert!(!ProgramConfig::requires_restart("stoptime"));
+        assert!(!ProgramConfig::requires_restart("exitcodes"));
+    }
+
+    #[test]
+    fn with_schedule() {
+        let c = Config::from_str("
+programs:
+  backup:
+    cmd: \"/usr/bin/backup\"
+    schedule: \"*/5 * * * *\"
+    overlap: queue").unwrap();
+        let p = &c.programs["backup"];
+        assert_eq!(p.overlap, OverlapPolicy::Queue);
+        let time = Local.with_ymd_and_hms(2026, 10, 19, 12, 3, 0).unwrap();
+        let next = p.schedule.as_ref().unwrap().next_after(&time).unwrap();
+        assert_eq!(next, Local.with_ymd_and_hms(2026, 10, 19, 12, 5, 0).unwrap());
+        let e = Config::from_str("
+programs:
+  backup:
+    cmd: \"/usr/bin/backup\"
+    schedule: \"*/5 * *\"
+    overlap: sometimes").unwrap_err();
+        assert_eq!(e.0.len(), 2);
+        assert_eq!(e.0[0].field.as_deref(), Some("schedule"));
+        assert_eq!(e.0[1].field.as_deref(), Some("overlap"));
+    }
+
+    #[test]
+    fn with_templates() {
+        let yaml = "
+defaults:
+  stoptime: 30
+  env:
+    LANG: C
+templates:
+  worker:
+    stopsignal: INT
+    env:
+      QUEUE: default
+  slow_worker:
+    extends: worker
+    stoptime: 60
+programs:
+  mail:
+    cmd: \"/usr/bin/mail-worker\"
+    extends: slow_worker
+    env:
+      QUEUE: mail
+  web:
+    cmd: \"/usr/bin/web\"";
+        let c = Config::from_str(yaml).unwrap();
+        let mail = &c.programs["mail"];
+        assert_eq!(mail.stoptime, 60);
+        assert_eq!(mail.stopsignal, nix::sys::signal::SIGINT);
+        assert_eq!(mail.env["LANG"], "C");
+        assert_eq!(mail.env["QUEUE"], "mail");
+        assert_eq!(c.programs["web"].stoptime, 30);
+        assert!(c.effective(&["mail"]).unwrap().contains("QUEUE: mail"));
+        let yaml = "
+templates:
+  a:
+    extends: b
+  b:
+    extends: a
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    extends: a";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn with_unknown_key() {
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    autorestat: never";
+        assert!(Config::from_str(yaml).is_ok());
+        let e = Config::from_str(&format!("strict: true\n{}", yaml)).unwrap_err().to_string();
+        assert!(e.contains("did you mean `autorestart`"));
+    }
+
+    #[test]
+    fn with_check() {
+        let path = std::env::temp_dir().join(format!("taskmaster-check-{}.yaml", std::process::id()));
+        fs::write(&path, "programs:\n  cat:\n    cmd: /bin/cat\n    autorestat: never\n").unwrap();
+        let path = path.to_str().unwrap();
+        assert!(Config::from_file_as(path, ConfigFormat::Yaml).is_ok());
+        let e = Config::check_file_as(path, ConfigFormat::Yaml).unwrap_err().to_string();
+        assert!(e.contains("did you mean `autorestart`"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn with_non_positive_numprocs() {
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    numprocs: 0";
+        assert!(Config::from_str(yaml).is_err());
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    numprocs: 2
+  cat0:
+    cmd: \"/bin/cat\"";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn with_strict_paths() {
+        let yaml = "
+strict: true
+programs:
+  cat:
+    cmd: \"cat -n\"
+    workingdir: /tmp";
+        assert!(Config::from_str(yaml).is_ok());
+        let yaml = "
+strict: true
+programs:
+  cat:
+    cmd: \"/nonexistent/cat\"";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn dump_config() {
+        let yaml = "
+programs:
+  vogsphere:
+    cmd: \"/usr/local/bin/vogsphere-worker %(process_num)d\"
+    numprocs: 2
+    umask: 077
+    stopsignal: USR1
+    healthcheck:
+      http: \"http://localhost:8080/health\"";
+        let c = Config::from_str(yaml).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&c.dump(cfg::DumpFormat::Json)).unwrap();
+        let p = &json["programs"]["vogsphere1"];
+        assert_eq!(p["cmd"], "/usr/local/bin/vogsphere-worker 1");
+        assert_eq!(p["umask"], "077");
+        assert_eq!(p["stopsignal"], "USR1");
+        assert_eq!(p["healthcheck"]["http"], "http://localhost:8080/health");
+        assert!(c.dump(cfg::DumpFormat::Yaml).contains("  vogsphere0:\n"));
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+    workingdir: /srv
+    env:
+      PORT: 80
+  vogsphere:
+    cmd: \"/usr/local/bin/vogsphere-worker %(process_num)d\"
+    numprocs: 2
+    stdout: /tmp/vogsphere_%(process_num)d.log
+    healthcheck:
+      cmd: \"/bin/true\"
+groups:
+  web: [nginx, vogsphere]";
+        let c = Config::from_str(yaml).unwrap();
+        let dumped = Config::from_str(&c.dump(cfg::DumpFormat::Yaml)).unwrap();
+        assert_eq!(dumped.groups["web"], c.groups["web"]);
+        let json = dumped.dump(cfg::DumpFormat::Json);
+        let dir = std::env::temp_dir().join(format!("taskmaster-dump-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.json");
+        fs::write(&path, json).unwrap();
+        let dumped = Config::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(dumped.programs["nginx"], c.programs["nginx"]);
+        assert_eq!(dumped.programs["vogsphere1"].diff(&c.programs["vogsphere1"]), vec!["group", "numprocs", "process_num"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn with_groups() {
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+  vogsphere:
+    cmd: \"/usr/local/bin/vogsphere-worker\"
+    numprocs: 2
+groups:
+  web: [nginx, vogsphere]";
+        let c = Config::from_str(&yaml.replace("worker\"", "worker %(group_name)s\"")).unwrap();
+        assert_eq!(c.programs["vogsphere1"].cmd, "/usr/local/bin/vogsphere-worker web");
+        assert_eq!(c.programs["vogsphere1"].group, "vogsphere");
+        assert_eq!(c.groups["web"], vec!["nginx", "vogsphere0", "vogsphere1"]);
+        assert_eq!(c.groups["vogsphere"], vec!["vogsphere0", "vogsphere1"]);
+        assert_eq!(c.groups["nginx"], vec!["nginx"]);
+    }
+
+    #[test]
+    fn with_invalid_groups() {
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+groups:
+  web: [nginx, vogsphere]";
+        assert!(Config::from_str(yaml).is_err());
+        let yaml = "
+programs:
+  nginx:
+    cmd: \"/usr/local/bin/nginx\"
+groups:
+  nginx: [nginx]";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn scale_program() {
+        let yaml = "
+programs:
+  web:
+    cmd: \"/usr/bin/web\"
+    depends_on: [worker]
+  worker:
+    cmd: \"/usr/bin/worker\"
+groups:
+  all: [worker, web]";
+        let mut c = Config::from_str(yaml).unwrap();
+        let (old, new) = c.scale("worker", 3).unwrap();
+        assert_eq!(old, vec!["worker"]);
+        assert_eq!(new, vec!["worker0", "worker1", "worker2"]);
+        assert_eq!(c.programs["worker2"].numprocs, 3);
+        assert!(!c.programs.contains_key("worker"));
+        assert_eq!(c.groups["all"], vec!["worker0", "worker1", "worker2", "web"]);
+        assert_eq!(c.programs["web"].depends_on, vec!["worker0", "worker1", "worker2"]);
+        c.scale("worker", 2).unwrap();
+        assert!(!c.programs.contains_key("worker2"));
+        assert!(c.scale("all", 2).is_err());
+        assert!(c.scale("worker", 0).is_err());
+    }
+
+    #[test]
+    fn with_interpolation() {
+        let yaml = "
+programs:
+  vogsphere:
+    cmd: \"/usr/local/bin/vogsphere-worker --shard %(process_num)d\"
+    numprocs: 2
+    numprocs_start: 1
+    process_name: \"%(program_name)s_%(process_num)02d\"
+    workingdir: \"%(here)s\"
+    stdout: \"/tmp/%(group_name)s-%(process_num)d.stdout\"
+    env:
+      WORKER: \"%(process_num)d\"";
+        let c = Config::from_str(yaml).unwrap();
+        let here = std::env::current_dir().unwrap().to_string_lossy().to_string();
+        let p = &c.programs["vogsphere_02"];
+        assert_eq!(p.cmd, "/usr/local/bin/vogsphere-worker --shard 2");
+        assert_eq!(p.process_num, 2);
+        assert_eq!(p.workingdir, Some(here));
+        assert!(p.stdout.is_path("/tmp/vogsphere-2.stdout"));
+        assert_eq!(p.env["WORKER"], "2");
+        assert!(c.programs.contains_key("vogsphere_01"));
+    }
+
+    #[test]
+    fn with_invalid_interpolation() {
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat %(nope)s\"";
+        assert!(Config::from_str(yaml).is_err());
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    numprocs: 2
+    process_name: cat";
+        assert!(Config::from_str(yaml).is_err());
+    }
+
+    #[test]
+    fn with_invalid_type() {
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    type: oneshot";
+        let c = Config::from_str(yaml);
+        assert!(c.is_err())
     }
 
     #[test]
@@ -466,6 +2824,13 @@ programs:
   cat:
     cmd: \"/bin/cat\"
     umask: error";
+        let c = Config::from_str(yaml);
+        assert!(c.is_err());
+        let yaml = "
+programs:
+  cat:
+    cmd: \"/bin/cat\"
+    umask: 8";
         let c = Config::from_str(yaml);
         assert!(c.is_err())
     }
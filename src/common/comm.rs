@@ -1,9 +1,13 @@
 use std::io::{Read, Result, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 
+/// Control socket of the daemon, relative to its working directory.
+pub const SOCKET_PATH: &str = "taskmaster.socket";
+
 //TODO: check stream result
+#[allow(clippy::unused_io_amount)]
 pub fn send_message(line: String) -> Result<()> {
-    let mut stream = UnixStream::connect("taskmaster.socket")?;
+    let mut stream = UnixStream::connect(SOCKET_PATH)?;
     let mut response = String::new();
     stream.write(line.as_bytes())?;
     stream.shutdown(std::net::Shutdown::Write)?;
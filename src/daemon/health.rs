@@ -0,0 +1,163 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use nix::sys::signal;
+
+use super::{status, ProcessInfo, ProcessStatus};
+use crate::cfg::{HealthCheck, Probe, RestartPolicy};
+
+/// Health check bookkeeping of a process.
+#[derive(Default)]
+pub struct Health {
+    pub last_check: Option<Instant>,
+    pub last_result: Option<Result<(), String>>,
+    /// Consecutive failed checks.
+    pub failures: i64,
+    pending: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Health {
+    pub fn status_str(&self, hc: &HealthCheck) -> String {
+        match &self.last_result {
+            Some(Ok(())) => ", health ok".to_string(),
+            Some(Err(e)) => format!(", health failed {}/{} ({})", self.failures, hc.retries, e),
+            None => String::new(),
+        }
+    }
+}
+
+fn run_cmd(cmd: &str, timeout: Duration) -> Result<(), String> {
+    let mut argv = cmd.split_whitespace();
+    let mut child = Command::new(argv.next().unwrap_or_default())
+        .args(argv)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let start = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(s)) if s.success() => return Ok(()),
+            Ok(Some(s)) => return Err(s.to_string()),
+            Ok(None) if start.elapsed() > timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err("timed out".to_string());
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+fn http_get(host: &str, port: u16, path: &str, timeout: Duration) -> Result<(), String> {
+    let addrs = (host.trim_start_matches('[').trim_end_matches(']'), port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?;
+    let mut error = format!("failed to resolve {}", host);
+    let mut stream = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(e) => error = e.to_string(),
+        }
+    }
+    let mut stream = stream.ok_or(error)?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    let _ = stream.take(1024).read_to_end(&mut response);
+    let response = String::from_utf8_lossy(&response);
+    let code = response
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .ok_or_else(|| "invalid http response".to_string())?;
+    if code.starts_with('2') || code.starts_with('3') {
+        Ok(())
+    } else {
+        Err(format!("http status {}", code))
+    }
+}
+
+fn probe(probe: Probe, timeout: Duration) -> Result<(), String> {
+    match probe {
+        Probe::Cmd(cmd) => run_cmd(&cmd, timeout),
+        Probe::Tcp(port) => TcpStream::connect_timeout(&SocketAddr::from(([127, 0, 0, 1], port)), timeout)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Probe::Unix(path) => UnixStream::connect(path)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Probe::Http { host, port, path } => http_get(&host, port, &path, timeout),
+    }
+}
+
+fn record(proc: &mut ProcessInfo, hc: &HealthCheck, result: Result<(), String>) {
+    match result {
+        Ok(()) => {
+            proc.health.failures = 0;
+            if proc.status == ProcessStatus::Unhealthy && proc.stop_time.is_none() {
+                proc.status = ProcessStatus::Running;
+            }
+        }
+        Err(_) => proc.health.failures += 1,
+    }
+    proc.health.last_result = Some(result);
+    if proc.status == ProcessStatus::Running && proc.health.failures >= hc.retries {
+        mark_unhealthy(proc);
+    }
+}
+
+/// Flag a process as UNHEALTHY and ask it to stop so it gets restarted,
+/// unless its restart policy is `never`.
+pub fn mark_unhealthy(proc: &mut ProcessInfo) {
+    proc.status = ProcessStatus::Unhealthy;
+    if proc.conf.autorestart != RestartPolicy::Never {
+        if let Some(pid) = proc.live_pid() {
+            let _ = signal::kill(pid, proc.conf.stopsignal);
+            proc.stop_time = Some(Instant::now());
+        }
+    }
+}
+
+/// Collect the result of the last probe of a RUNNING or UNHEALTHY process and
+/// launch the next one once `interval` has elapsed.
+pub fn check(proc: &mut ProcessInfo) {
+    let hc = match &proc.conf.healthcheck {
+        Some(hc) => hc.clone(),
+        None => return,
+    };
+    if !matches!(proc.status, ProcessStatus::Running | ProcessStatus::Unhealthy) {
+        return;
+    }
+    if let Some(handle) = proc.health.pending.take() {
+        if !handle.is_finished() {
+            proc.health.pending = Some(handle);
+            return;
+        }
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err("health check panicked".to_string()));
+        record(proc, &hc, result);
+    }
+    match proc.health.last_check {
+        Some(t) if !status::check_time(t, hc.interval) => (),
+        _ => {
+            let timeout = Duration::from_secs(hc.timeout.unsigned_abs());
+            let p = hc.probe.clone();
+            proc.health.last_check = Some(Instant::now());
+            proc.health.pending = Some(thread::spawn(move || probe(p, timeout)));
+        }
+    }
+}
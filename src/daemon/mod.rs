@@ -1,18 +1,30 @@
 use std::collections::HashMap;
 use std::fmt::{self, Display};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::process::{Child, ExitStatus};
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::cfg::{Config, ProgramConfig};
+use nix::unistd::Pid;
 
+use crate::cfg::{Config, ConfigFormat, ProgramConfig};
+
+pub mod health;
+pub mod notify;
+pub mod pidfile;
+pub mod reaper;
 pub mod reload;
 pub mod restart;
+pub mod rolling;
+pub mod scale;
+pub mod schedule;
+pub mod select;
 pub mod shutdown;
 pub mod start;
 pub mod status;
 pub mod stop;
+pub mod watch;
 
 #[derive(Debug, PartialEq)]
 pub enum ProcessStatus {
@@ -23,6 +35,7 @@ pub enum ProcessStatus {
     Exited,
     Backoff,
     Fatal,
+    Unhealthy,
     Unknown
 }
 
@@ -36,6 +49,7 @@ impl Display for ProcessStatus {
             ProcessStatus::Exited => write!(f, "EXITED"),
             ProcessStatus::Backoff => write!(f, "BACKOFF"),
             ProcessStatus::Fatal => write!(f, "FATAL"),
+            ProcessStatus::Unhealthy => write!(f, "UNHEALTHY"),
             ProcessStatus::Unknown => write!(f, "Unknown"),
         }
     }
@@ -47,89 +61,261 @@ pub struct ProcessInfo {
     pub child: Option<Child>,
     pub status: ProcessStatus,
     pub start_time: Option<Instant>,
+    /// Set once the process exited, even if its exit status is unknown.
     pub exit_time: Option<Instant>,
     pub stop_time: Option<Instant>,
     pub start_nb: i64,
+    /// Starts in a row that exited before the program was RUNNING.
+    pub retries: i64,
     pub exit_status: Option<ExitStatus>,
+    /// Orphaned descendants reparented to the daemon, matched by process
+    /// group or session.
+    pub descendants: Vec<Pid>,
+    /// Pid read from the pidfile of a forking program.
+    pub main_pid: Option<Pid>,
+    pub health: health::Health,
+    pub notify: Option<notify::Notify>,
+    /// Start requested, waiting for the dependencies to be RUNNING.
+    pub start_pending: bool,
+    /// Stop requested, waiting for the dependents to be stopped.
+    pub stop_pending: bool,
+    /// No longer in the config, dropped once stopped.
+    pub remove_pending: bool,
+    pub job: schedule::Job,
 }
 
 impl ProcessInfo {
+    pub fn new(conf: ProgramConfig) -> ProcessInfo {
+        ProcessInfo {
+            conf,
+            child: None,
+            status: ProcessStatus::Stopped,
+            start_time: None,
+            start_nb: 0,
+            retries: 0,
+            exit_time: None,
+            stop_time: None,
+            exit_status: None,
+            descendants: Vec::new(),
+            main_pid: None,
+            health: health::Health::default(),
+            notify: None,
+            start_pending: false,
+            stop_pending: false,
+            remove_pending: false,
+            job: schedule::Job::default(),
+        }
+    }
+
     fn status_str(&self) -> String {
         //TODO: add padding to improve readbility
         format!(
-            "{:33} {:8} {}\n",
+            "{:33} {:8} {}{}{}{}{}\n",
             self.conf.name,
             self.status,
             self.pid_str(),
+            self.descendants_str(),
+            match &self.conf.healthcheck {
+                Some(hc) => self.health.status_str(hc),
+                None => String::new(),
+            },
+            match &self.notify {
+                Some(n) => n.status_str(),
+                None => String::new(),
+            },
+            match &self.conf.schedule {
+                Some(_) => self.job.status_str(self),
+                None => String::new(),
+            },
         )
     }
 
     fn pid_str(&self) -> String {
         match self.status {
-            ProcessStatus::Starting => format!(""),
-            ProcessStatus::Running | ProcessStatus::Stopping => format!("pid {:8}, {}", match &self.child {
-                Some(c) => c.id(),
+            ProcessStatus::Starting => String::new(),
+            ProcessStatus::Running | ProcessStatus::Stopping | ProcessStatus::Unhealthy => format!("pid {:8}, {}", match self.pid() {
+                Some(p) => p.as_raw(),
                 None => 0
             }
             , self.uptime_str()),
             //TODO: print exit time when available
+            ProcessStatus::Stopped if self.start_pending => "Waiting for dependencies".to_string(),
             ProcessStatus::Stopped => format!("{:12}", "Not started"),
-            ProcessStatus::Exited => format!("{}", self.exittime_str()),
-            ProcessStatus::Backoff | ProcessStatus::Fatal => format!("Exited too quickly"),
-            ProcessStatus::Unknown => todo!(),
+            ProcessStatus::Exited => self.exittime_str(),
+            ProcessStatus::Backoff | ProcessStatus::Fatal => "Exited too quickly".to_string(),
+            ProcessStatus::Unknown => "State unknown".to_string(),
+        }
+    }
+
+    fn descendants_str(&self) -> String {
+        if self.descendants.is_empty() {
+            return String::new();
+        }
+        let pids: Vec<String> = self.descendants.iter().map(|p| p.to_string()).collect();
+        format!(", adopted {}", pids.join(","))
+    }
+
+    pub fn is_alive(&self) -> bool {
+        matches!(
+            self.status,
+            ProcessStatus::Starting
+                | ProcessStatus::Running
+                | ProcessStatus::Stopping
+                | ProcessStatus::Unhealthy
+        )
+    }
+
+    /// Whether the program or one of its adopted descendants still runs.
+    pub fn has_processes(&self) -> bool {
+        self.is_alive() || !self.descendants.is_empty()
+    }
+
+    /// Pid of the supervised process: the one read from the pidfile for
+    /// forking programs, the direct child otherwise.
+    pub fn pid(&self) -> Option<Pid> {
+        self.main_pid
+            .or_else(|| self.child.as_ref().map(|c| Pid::from_raw(c.id() as i32)))
+    }
+
+    /// Pid of the supervised process while it can be signalled: alive and
+    /// not reaped yet, as the pid of a reaped process may have been reused.
+    pub fn live_pid(&self) -> Option<Pid> {
+        match self.exit_time {
+            None if self.is_alive() => self.pid(),
+            _ => None,
         }
     }
 
     fn uptime_str(&self) -> String {
         match self.start_time {
-            Some(time) => {
-                let s = time.elapsed().as_secs();
-                let m = s / 60;
-                let h = s / 3600;
-                format!("uptime {:02}:{:02}:{:02}", h, m - (60 * h), s - (3600 * h))
-            },
-            None => format!("program not started")
+            Some(time) => format!("uptime {}", hms_str(time.elapsed())),
+            None => "program not started".to_string()
         }
     }
 
     fn exittime_str(&self) -> String {
-        todo!()
+        match (self.exit_status, self.exit_time) {
+            (Some(status), Some(time)) => format!("{}, {} ago", status, hms_str(time.elapsed())),
+            (None, Some(time)) => format!("exit status unknown, {} ago", hms_str(time.elapsed())),
+            _ => "exit status unknown".to_string(),
+        }
     }
 }
 
+fn hms_str(d: Duration) -> String {
+    let s = d.as_secs();
+    let m = s / 60;
+    let h = s / 3600;
+    format!("{:02}:{:02}:{:02}", h, m - (60 * h), s - (60 * m))
+}
+
 pub type ProcessList = HashMap<String, ProcessInfo>;
 
+/// Delay between two supervision passes when no command is pending.
+const TICK: Duration = Duration::from_millis(100);
+
 pub struct Daemon {
     pub conf: Config,
     pub listener: UnixListener,
     pub proc_list: ProcessList,
+    pub shutting_down: bool,
+    pub cfg_path: String,
+    pub cfg_format: ConfigFormat,
+    /// `numprocs` overrides set with the `scale` command, by program.
+    pub scales: HashMap<String, scale::Scale>,
+    pub rollouts: Vec<rolling::Rollout>,
+    /// Watcher of the config files, with `watch_config`.
+    pub watcher: Option<watch::Watcher>,
+    /// Config before the last reload, for `rollback`.
+    pub last_good: Option<Config>,
+    pub transaction: Option<reload::Transaction>,
+    /// Outcome of the last atomic reload, shown by `status`.
+    pub last_transaction: Option<String>,
 }
 
 impl Daemon {
+    pub fn new(conf: Config, listener: UnixListener, cfg_path: String, cfg_format: ConfigFormat) -> Daemon {
+        Daemon {
+            conf,
+            listener,
+            proc_list: HashMap::new(),
+            shutting_down: false,
+            cfg_path,
+            cfg_format,
+            scales: HashMap::new(),
+            rollouts: Vec::new(),
+            watcher: None,
+            last_good: None,
+            transaction: None,
+            last_transaction: None,
+        }
+    }
+
     pub fn gen_proc_list(&mut self) {
         for (name, prog_conf) in &self.conf.programs {
-            let proc_info: ProcessInfo = ProcessInfo {
-                conf: prog_conf.clone(),
-                child: None,
-                status: ProcessStatus::Stopped,
-                start_time: None,
-                start_nb: 0,
-                exit_time: None,
-                stop_time: None,
-                exit_status: None,
-            };
-            self.proc_list.entry(name.to_string()).or_insert(proc_info);
+            self.proc_list
+                .entry(name.to_string())
+                .or_insert_with(|| ProcessInfo::new(prog_conf.clone()));
         }
     }
 
     //TODO: start process that needs to get started on boot
     pub fn run(&mut self) {
         self.gen_proc_list();
+        watch::refresh(self);
+        if let Err(e) = reaper::set_child_subreaper() {
+            println!("daemon: failed to become a child subreaper: {}", e);
+        }
+        self.listener
+            .set_nonblocking(true)
+            .expect("failed to set socket non-blocking");
         loop {
-            let (mut stream, _) = self.listener.accept().expect("fail accept");
-            let cmd = self.recv_cmd(&mut stream);
-            let response = self.run_cmd(cmd.to_string());
-            self.send_resp(response, &mut stream);
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream
+                        .set_nonblocking(false)
+                        .expect("failed to set stream blocking");
+                    let cmd = self.recv_cmd(&stream);
+                    let response = self.run_cmd(cmd.to_string());
+                    self.send_resp(response, &stream);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(TICK),
+                Err(e) => panic!("fail accept: {}", e),
+            }
+            self.update();
+        }
+    }
+
+    /// Reap exited children and refresh the state of every process.
+    pub fn update(&mut self) {
+        reaper::reap(&mut self.proc_list);
+        for proc in self.proc_list.values_mut() {
+            notify::poll(proc);
+            proc.status = status::check_state(proc);
+        }
+        // after the exits are seen, so the orphans of a launcher are adopted
+        // before it is restarted
+        reaper::adopt(&mut self.proc_list);
+        for proc in self.proc_list.values_mut() {
+            notify::check_watchdog(proc);
+            health::check(proc);
+            if !self.shutting_down {
+                restart::autorestart(proc);
+            }
+            stop::kill_overdue(proc);
+        }
+        self.proc_list.retain(|_, p| !p.remove_pending || p.has_processes());
+        let pending = start::start_pending(self)
+            + &stop::stop_pending(self)
+            + &rolling::advance(self)
+            + &watch::check(self)
+            + &reload::check_transaction(self)
+            + &schedule::run_due(self);
+        for line in pending.lines() {
+            println!("daemon: {}", line);
+        }
+        if self.shutting_down && self.proc_list.values().all(|p| !p.has_processes() && !p.stop_pending) {
+            shutdown::exit();
         }
     }
 
@@ -141,18 +327,40 @@ impl Daemon {
         } else {
             ""
         };
+        let args = argv.get(1..).unwrap_or_default();
         match cmd {
-            "start" => start::start(argv[1..].to_vec(), self),
-            "status" => status::status(argv[1..].to_vec(), &self),
-            "stop" => stop::stop(argv[1..].to_vec(), self),
-            //"restart" => launch_proces::restart(command, conf),
-            //"reload" => launch_proces::reload(command, conf),
-            "shutdown" => shutdown::shutdown(),
+            "start" => self.run_selected(args, start::start),
+            "status" if args.first() == Some(&"--groups") => status::groups_status(&args[1..], self),
+            "status" => self.run_selected(args, |a, d| status::status(a, d)),
+            "stop" => self.run_selected(args, stop::stop),
+            "restart" if args.first() == Some(&"--rolling") => rolling::rolling(&args[1..], self),
+            "restart" => self.run_selected(args, restart::restart),
+            "scale" => scale::scale(args.to_vec(), self),
+            "reload" if args == ["--dry-run"] => reload::reread(self),
+            "reload" if args == ["--atomic"] => reload::reload(self, true),
+            "reload" => reload::reload(self, false),
+            "rollback" => reload::rollback(self),
+            "reread" => reload::reread(self),
+            "config" => match self.conf.effective(args) {
+                Ok(yaml) => yaml,
+                Err(e) => format!("ERROR ({})\n", e),
+            },
+            "shutdown" => shutdown::shutdown(self),
             _ => "".to_string(),
         }
     }
 
-    pub fn recv_cmd(&mut self, mut stream: &UnixStream) -> String {
+    /// Run a command on the processes matched by its arguments, or on all
+    /// of them when there are none.
+    fn run_selected(&mut self, args: &[&str], cmd: fn(Vec<&str>, &mut Daemon) -> String) -> String {
+        let (names, errors) = select::select(args, self);
+        if !args.is_empty() && names.is_empty() {
+            return errors;
+        }
+        errors + &cmd(names.iter().map(String::as_str).collect(), self)
+    }
+
+    pub fn recv_cmd(&self, mut stream: &UnixStream) -> String {
         let mut cmd = String::new();
         stream
             .read_to_string(&mut cmd)
@@ -161,8 +369,25 @@ impl Daemon {
         cmd
     }
 
-    pub fn send_resp(&mut self, response: String, mut stream: &UnixStream) -> () {
-        stream.write(response.as_bytes()).expect("failed to write");
+    pub fn send_resp(&self, response: String, mut stream: &UnixStream) {
+        stream.write_all(response.as_bytes()).expect("failed to write");
         println!("daemon: sending response: {}", response);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ProcessInfo, ProcessStatus};
+    use crate::cfg::Config;
+
+    #[test]
+    fn unknown_status() {
+        let c = Config::from_str("
+programs:
+  cat:
+    cmd: /bin/cat").unwrap();
+        let mut proc = ProcessInfo::new(c.programs["cat"].clone());
+        proc.status = ProcessStatus::Unknown;
+        assert!(proc.status_str().ends_with(" State unknown\n"));
+    }
+}
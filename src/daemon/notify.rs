@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use nix::unistd::getpid;
+
+use super::{health, status, ProcessInfo, ProcessStatus};
+
+/// Datagram socket a program reports its state on, sd_notify style.
+pub struct Notify {
+    socket: UnixDatagram,
+    path: PathBuf,
+    pub ready: bool,
+    pub status_text: Option<String>,
+    pub last_watchdog: Option<Instant>,
+    pub watchdog_expired: bool,
+}
+
+impl Notify {
+    pub fn bind(name: &str) -> io::Result<Notify> {
+        let path = env::temp_dir().join(format!("taskmasterd-{}-{}.notify", getpid(), name));
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Notify {
+            socket,
+            path,
+            ready: false,
+            status_text: None,
+            last_watchdog: None,
+            watchdog_expired: false,
+        })
+    }
+
+    /// Variables telling the program where to send its notifications.
+    pub fn env(&self, watchdog: Option<i64>) -> HashMap<String, String> {
+        let mut env = HashMap::from([(
+            "NOTIFY_SOCKET".to_string(),
+            self.path.to_string_lossy().to_string(),
+        )]);
+        if let Some(secs) = watchdog {
+            env.insert("WATCHDOG_USEC".to_string(), (secs * 1_000_000).to_string());
+        }
+        env
+    }
+
+    pub fn status_str(&self) -> String {
+        match &self.status_text {
+            Some(s) => format!(", \"{}\"", s),
+            None => String::new(),
+        }
+    }
+
+    fn handle(&mut self, line: &str) {
+        match line.split_once('=') {
+            Some(("READY", "1")) => {
+                self.ready = true;
+                self.last_watchdog = Some(Instant::now());
+            }
+            Some(("STATUS", s)) => self.status_text = Some(s.to_string()),
+            Some(("WATCHDOG", "1")) => self.last_watchdog = Some(Instant::now()),
+            _ => (),
+        }
+    }
+}
+
+impl Drop for Notify {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Read every pending notification of a process.
+pub fn poll(proc: &mut ProcessInfo) {
+    let notify = match &mut proc.notify {
+        Some(n) => n,
+        None => return,
+    };
+    let mut buf = [0u8; 4096];
+    while let Ok(len) = notify.socket.recv(&mut buf) {
+        let msg = String::from_utf8_lossy(&buf[..len]).to_string();
+        for line in msg.lines() {
+            notify.handle(line);
+        }
+    }
+}
+
+/// Mark a RUNNING process unhealthy when it missed its watchdog interval.
+pub fn check_watchdog(proc: &mut ProcessInfo) {
+    let secs = match proc.conf.watchdog {
+        Some(s) => s,
+        None => return,
+    };
+    if proc.status != ProcessStatus::Running {
+        return;
+    }
+    let notify = match &mut proc.notify {
+        Some(n) => n,
+        None => return,
+    };
+    match notify.last_watchdog {
+        Some(t) if status::check_time(t, secs) => notify.watchdog_expired = true,
+        _ => return,
+    }
+    health::mark_unhealthy(proc);
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::sys::signal;
+use nix::unistd::Pid;
+
+use super::{status, ProcessInfo, ProcessStatus};
+
+fn read_pidfile(path: &str) -> Option<Pid> {
+    match fs::read_to_string(path).ok()?.trim().parse::<i32>() {
+        Ok(pid) if pid > 0 => Some(Pid::from_raw(pid)),
+        _ => None,
+    }
+}
+
+/// Remove a pidfile left over by a previous run so its pid is not picked up.
+pub fn clear(proc: &ProcessInfo) {
+    if let Some(path) = &proc.conf.pidfile {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Next state of a forking program whose launcher has not handed over the
+/// pid of the process to supervise yet.
+pub fn check_launcher(proc: &mut ProcessInfo) -> ProcessStatus {
+    if let Some(c) = &mut proc.child {
+        if let Ok(Some(s)) = c.try_wait() {
+            proc.exit_status = Some(s);
+            proc.exit_time = Some(Instant::now());
+        }
+    }
+    if let Some(s) = proc.exit_status {
+        if !s.success() {
+            return ProcessStatus::Backoff;
+        }
+    }
+    let path = proc.conf.pidfile.as_deref().unwrap_or_default();
+    if let Some(pid) = read_pidfile(path) {
+        proc.main_pid = Some(pid);
+        proc.exit_status = None;
+        proc.exit_time = None;
+        proc.descendants.retain(|p| *p != pid);
+        return ProcessStatus::Starting;
+    }
+    match proc.start_time {
+        Some(t) if status::check_time(t, proc.conf.starttime) => ProcessStatus::Backoff,
+        _ => ProcessStatus::Starting,
+    }
+}
+
+/// Record the exit of a supervised pid the daemon is not the parent of, and
+/// therefore cannot reap.
+pub fn check_alive(proc: &mut ProcessInfo) {
+    let pid = match proc.main_pid {
+        Some(p) => p,
+        None => return,
+    };
+    if proc.exit_time.is_none() && signal::kill(pid, None) == Err(Errno::ESRCH) {
+        // the exit status of a process we did not wait for is unknown
+        proc.exit_time = Some(Instant::now());
+    }
+}
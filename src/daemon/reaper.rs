@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{getpid, Pid};
+
+use super::ProcessList;
+
+struct ProcStat {
+    ppid: Pid,
+    pgrp: Pid,
+    session: Pid,
+}
+
+/// Mark the daemon as a child subreaper so orphaned descendants of the
+/// programs get reparented to it instead of init.
+pub fn set_child_subreaper() -> io::Result<()> {
+    let ret = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Reap every zombie child, direct or adopted, and record the exit status of
+/// the direct ones.
+pub fn reap(proc_list: &mut ProcessList) {
+    loop {
+        let (pid, raw) = match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) => (pid, code << 8),
+            Ok(WaitStatus::Signaled(pid, sig, core)) => (pid, sig as i32 | if core { 0x80 } else { 0 }),
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        };
+        record_exit(proc_list, pid, ExitStatus::from_raw(raw));
+    }
+}
+
+fn record_exit(proc_list: &mut ProcessList, pid: Pid, status: ExitStatus) {
+    for proc in proc_list.values_mut() {
+        if proc.pid() == Some(pid) {
+            proc.exit_status = Some(status);
+            proc.exit_time = Some(Instant::now());
+            return;
+        }
+        proc.descendants.retain(|p| *p != pid);
+    }
+}
+
+/// Attribute the processes reparented to the daemon to the program whose
+/// process group or session they belong to.
+pub fn adopt(proc_list: &mut ProcessList) {
+    let daemon_pid = getpid();
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_str().and_then(|s| s.parse::<i32>().ok()) {
+            Some(p) => Pid::from_raw(p),
+            None => continue,
+        };
+        let stat = match read_stat(pid) {
+            Some(s) if s.ppid == daemon_pid => s,
+            _ => continue,
+        };
+        if proc_list
+            .values()
+            .any(|p| p.pid() == Some(pid) || p.descendants.contains(&pid))
+        {
+            continue;
+        }
+        if let Some(owner) = proc_list
+            .values_mut()
+            .find(|p| matches!(p.pid(), Some(l) if l == stat.pgrp || l == stat.session))
+        {
+            owner.descendants.push(pid);
+        }
+    }
+}
+
+fn read_stat(pid: Pid) -> Option<ProcStat> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name may contain spaces, fields start after its closing paren
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |i: usize| fields.get(i)?.parse::<i32>().ok().map(Pid::from_raw);
+    Some(ProcStat {
+        ppid: field(1)?,
+        pgrp: field(2)?,
+        session: field(3)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adopt, read_stat};
+    use crate::cfg::Config;
+    use crate::daemon::{ProcessInfo, ProcessList};
+    use nix::unistd::{getpgrp, getpid, getppid, getsid, Pid};
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn stat_of_self() {
+        let stat = read_stat(getpid()).unwrap();
+        assert_eq!(stat.ppid, getppid());
+        assert_eq!(stat.pgrp, getpgrp());
+        assert_eq!(stat.session, getsid(None).unwrap());
+        assert!(read_stat(Pid::from_raw(i32::MAX)).is_none());
+    }
+
+    #[test]
+    fn adopt_by_process_group() {
+        let c = Config::from_str("
+programs:
+  daemon:
+    cmd: /bin/sleep 10").unwrap();
+        let mut leader = Command::new("/bin/sleep").arg("10").process_group(0).spawn().unwrap();
+        let leader_pid = Pid::from_raw(leader.id() as i32);
+        // stands for a process left behind by the program, in its group
+        let mut orphan = Command::new("/bin/sleep").arg("10").process_group(leader.id() as i32).spawn().unwrap();
+        let mut proc = ProcessInfo::new(c.programs["daemon"].clone());
+        proc.main_pid = Some(leader_pid);
+        let mut proc_list = ProcessList::new();
+        proc_list.insert("daemon".to_string(), proc);
+        adopt(&mut proc_list);
+        adopt(&mut proc_list);
+        assert_eq!(proc_list["daemon"].descendants, [Pid::from_raw(orphan.id() as i32)]);
+        leader.kill().unwrap();
+        orphan.kill().unwrap();
+        leader.wait().unwrap();
+        orphan.wait().unwrap();
+    }
+}
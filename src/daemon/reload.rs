@@ -1,3 +1,330 @@
-use crate::cfg::Config;
+use std::mem;
+use std::time::{Duration, Instant};
 
-fn reload(command: Vec<&str>, conf: &Config) -> () {}
+use crate::cfg::{Config, ProgramConfig};
+
+use super::{restart, rolling, start, stop, watch, Daemon, ProcessInfo, ProcessStatus};
+
+/// Margin over `starttime` for a process of an atomic reload to be RUNNING.
+const GRACE: Duration = Duration::from_secs(1);
+
+/// Read the config file again, with the `scale` overrides that still apply:
+/// those whose program kept the `numprocs` they were set from. Returns the
+/// config and the programs of the kept overrides.
+fn read_config(daemon: &Daemon) -> Result<(Config, Vec<String>), String> {
+    let mut conf = match Config::from_file_as(&daemon.cfg_path, daemon.cfg_format) {
+        Ok(c) => c,
+        Err(e) => return Err(format!("ERROR (invalid config)\n{}\n", e)),
+    };
+    let kept: Vec<String> = daemon
+        .scales
+        .iter()
+        .filter(|(program, s)| {
+            matches!(conf.groups.get(*program), Some(m) if m.len() as i64 == s.from
+                && conf.programs[&m[0]].group == **program)
+        })
+        .map(|(program, _)| program.clone())
+        .collect();
+    for program in &kept {
+        let _ = conf.scale(program, daemon.scales[program].to);
+    }
+    Ok((conf, kept))
+}
+
+/// Programs of the process list that a new config adds, changes and removes.
+struct Changes {
+    added: Vec<String>,
+    /// With their changed fields, and whether they need a restart to apply.
+    changed: Vec<(String, Vec<String>, bool)>,
+    removed: Vec<String>,
+}
+
+fn changes(daemon: &Daemon, conf: &Config) -> Changes {
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for name in conf.start_order() {
+        match daemon.proc_list.get(&name) {
+            None => added.push(name),
+            Some(proc) => {
+                let fields = proc.conf.diff(&conf.programs[&name]);
+                if !fields.is_empty() {
+                    let restart = fields.iter().any(|f| ProgramConfig::requires_restart(f));
+                    changed.push((name, fields, restart));
+                }
+            }
+        }
+    }
+    let mut removed: Vec<String> = daemon
+        .conf
+        .programs
+        .keys()
+        .filter(|name| !conf.programs.contains_key(*name) && daemon.proc_list.contains_key(*name))
+        .cloned()
+        .collect();
+    removed.sort();
+    Changes { added, changed, removed }
+}
+
+/// A program is `changed` when it must restart for its new config to apply,
+/// and `updated` when the config applies in place.
+fn describe_change(name: &str, fields: &[String], restart: bool) -> String {
+    let change = if restart { "changed" } else { "updated" };
+    format!("{}: {} ({})\n", name, change, fields.join(", "))
+}
+
+/// Show what a reload would change, without applying it.
+pub fn reread(daemon: &Daemon) -> String {
+    let conf = match read_config(daemon) {
+        Ok((c, _)) => c,
+        Err(e) => return e,
+    };
+    let Changes { added, changed, removed } = changes(daemon, &conf);
+    let mut response = String::new();
+    for name in added {
+        response += &format!("{}: added\n", name);
+    }
+    for (name, fields, restart) in &changed {
+        response += &describe_change(name, fields, *restart);
+    }
+    for name in removed {
+        response += &format!("{}: removed\n", name);
+    }
+    if response.is_empty() {
+        response += "No config updates to processes\n";
+    }
+    response
+}
+
+/// Read the config file again, add the new programs, restart the changed
+/// ones and stop the removed ones. Changes to fields that do not need a
+/// restart are applied in place. Changed programs with several instances
+/// are restarted one instance at a time, unless `atomic` where the changed
+/// programs are restarted at once and rolled back to their previous config
+/// if they do not reach RUNNING. `scale` overrides are kept unless the
+/// `numprocs` of their program changed in the file.
+pub fn reload(daemon: &mut Daemon, atomic: bool) -> String {
+    let (conf, kept) = match read_config(daemon) {
+        Ok(c) => c,
+        Err(e) => return e,
+    };
+    daemon.scales.retain(|program, _| kept.contains(program));
+    let (mut response, restarted, old) = apply(daemon, conf, atomic);
+    if atomic && !restarted.is_empty() {
+        let programs = restarted
+            .into_iter()
+            .filter_map(|name| old.programs.get(&name).map(|c| (name, c.clone())))
+            .collect();
+        daemon.transaction = Some(Transaction { began: Instant::now(), programs, failures: Vec::new() });
+        daemon.last_transaction = Some("in progress".to_string());
+        response += "atomic reload in progress, see status for its outcome\n";
+    } else if atomic {
+        daemon.last_transaction = Some("done".to_string());
+    }
+    daemon.last_good = Some(old);
+    response
+}
+
+/// Go back to the config before the last reload.
+pub fn rollback(daemon: &mut Daemon) -> String {
+    let conf = match daemon.last_good.take() {
+        Some(c) => c,
+        None => return "ERROR (no previous config)\n".to_string(),
+    };
+    if daemon.transaction.take().is_some() {
+        daemon.last_transaction = Some("rolled back (rollback command)".to_string());
+    }
+    let (response, _, _) = apply(daemon, conf, false);
+    "rolled back to the previous config\n".to_string() + &response
+}
+
+/// Replace the config of the daemon, returning the response, the programs
+/// restarted at once and the replaced config.
+fn apply(daemon: &mut Daemon, conf: Config, atomic: bool) -> (String, Vec<String>, Config) {
+    let Changes { added, changed, removed } = changes(daemon, &conf);
+    let old = mem::replace(&mut daemon.conf, conf);
+    watch::refresh(daemon);
+    let mut response = String::new();
+    let mut autostarted = Vec::new();
+    for name in added {
+        response += &format!("{}: added\n", name);
+        let conf = daemon.conf.programs[&name].clone();
+        // a scheduled program is started by its schedule
+        if conf.autostart && conf.schedule.is_none() {
+            autostarted.push(name.clone());
+        }
+        daemon.proc_list.insert(name, ProcessInfo::new(conf));
+    }
+    let mut restarted = Vec::new();
+    let mut rolled = Vec::new();
+    for (name, fields, restart) in changed {
+        response += &describe_change(&name, &fields, restart);
+        let conf = daemon.conf.programs[&name].clone();
+        let proc = daemon.proc_list.get_mut(&name).expect("changed program should be in the process list");
+        if restart && proc.is_alive() && conf.numprocs > 1 && !atomic {
+            rolled.push(name);
+        } else if restart && proc.is_alive() {
+            restarted.push(name);
+        }
+        // without a restart, the process picks the new config up as is
+        proc.conf = conf;
+        if fields.iter().any(|f| f == "schedule") {
+            proc.job.next_run = None;
+        }
+    }
+    for name in removed {
+        let proc = daemon.proc_list.get_mut(&name).expect("removed program should be in the process list");
+        response += &format!("{}: removed\n", name);
+        if proc.has_processes() {
+            response += &stop::stop_program(name.clone(), proc);
+        }
+        proc.start_pending = false;
+        proc.remove_pending = true;
+    }
+    if !restarted.is_empty() {
+        response += &restart::restart(restarted.iter().map(String::as_str).collect(), daemon);
+    }
+    if !rolled.is_empty() {
+        daemon.rollouts.push(rolling::Rollout::new(rolled, 1, true));
+        response += &rolling::advance(daemon);
+    }
+    if !autostarted.is_empty() {
+        response += &start::start(autostarted.iter().map(String::as_str).collect(), daemon);
+    }
+    (response, restarted, old)
+}
+
+/// Programs restarted by an atomic reload, waiting to reach RUNNING.
+pub struct Transaction {
+    began: Instant,
+    /// With their config before the reload.
+    programs: Vec<(String, ProgramConfig)>,
+    /// Programs rolled back so far, with the reason.
+    failures: Vec<String>,
+}
+
+/// Why a program restarted by an atomic reload failed, if it did.
+fn failure(proc: &ProcessInfo, began: Instant) -> Option<Result<(), String>> {
+    if proc.start_pending || proc.stop_pending || matches!(proc.status, ProcessStatus::Stopping | ProcessStatus::Stopped) {
+        return None;
+    }
+    let start = match proc.start_time {
+        Some(t) if t >= began => t,
+        _ => return None,
+    };
+    match proc.status {
+        ProcessStatus::Running => Some(Ok(())),
+        ProcessStatus::Starting if start.elapsed() > Duration::from_secs(proc.conf.starttime.unsigned_abs()) + GRACE => {
+            Some(Err(format!("not RUNNING after {}s", proc.conf.starttime)))
+        }
+        ProcessStatus::Starting => None,
+        _ => Some(Err(format!("{}", proc.status))),
+    }
+}
+
+/// Roll the programs of an atomic reload that failed to start back to their
+/// previous config, once each of them is RUNNING or failed.
+pub fn check_transaction(daemon: &mut Daemon) -> String {
+    let mut transaction = match daemon.transaction.take() {
+        Some(t) => t,
+        None => return String::new(),
+    };
+    let mut response = String::new();
+    let mut failed = Vec::new();
+    transaction.programs.retain(|(name, old)| {
+        let result = match daemon.proc_list.get(name) {
+            Some(proc) => failure(proc, transaction.began),
+            None => Some(Ok(())),
+        };
+        match result {
+            None => true,
+            Some(Ok(())) => false,
+            Some(Err(reason)) => {
+                response += &format!("{}: rolled back ({})\n", name, reason);
+                transaction.failures.push(format!("{}: {}", name, reason));
+                failed.push((name.clone(), old.clone()));
+                false
+            }
+        }
+    });
+    for (name, old) in &failed {
+        daemon.conf.programs.insert(name.clone(), old.clone());
+        if let Some(proc) = daemon.proc_list.get_mut(name) {
+            proc.conf = old.clone();
+        }
+    }
+    if !failed.is_empty() {
+        response += &restart::restart(failed.iter().map(|(n, _)| n.as_str()).collect(), daemon);
+    }
+    if transaction.programs.is_empty() {
+        let outcome = match transaction.failures.as_slice() {
+            [] => "done".to_string(),
+            failures => format!("rolled back ({})", failures.join(", ")),
+        };
+        response += &format!("atomic reload {}\n", outcome);
+        daemon.last_transaction = Some(outcome);
+    } else {
+        daemon.transaction = Some(transaction);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{failure, reload};
+    use crate::cfg::{Config, ConfigFormat};
+    use crate::daemon::{Daemon, ProcessInfo, ProcessStatus};
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn transaction_failure() {
+        let c = Config::from_str("
+programs:
+  web:
+    cmd: /bin/cat
+    starttime: 1").unwrap();
+        let mut proc = ProcessInfo::new(c.programs["web"].clone());
+        let began = Instant::now();
+        // not started since the reload
+        proc.status = ProcessStatus::Running;
+        proc.start_time = Some(began - Duration::from_secs(5));
+        assert_eq!(failure(&proc, began), None);
+        proc.start_time = Some(began);
+        proc.start_pending = true;
+        assert_eq!(failure(&proc, began), None);
+        proc.start_pending = false;
+        assert_eq!(failure(&proc, began), Some(Ok(())));
+        proc.status = ProcessStatus::Starting;
+        assert_eq!(failure(&proc, began), None);
+        proc.status = ProcessStatus::Fatal;
+        assert_eq!(failure(&proc, began), Some(Err("FATAL".to_string())));
+        let began = began - Duration::from_secs(3);
+        proc.status = ProcessStatus::Starting;
+        proc.start_time = Some(began);
+        assert_eq!(failure(&proc, began), Some(Err("not RUNNING after 1s".to_string())));
+    }
+
+    #[test]
+    fn reload_invalid_config() {
+        let dir = std::env::temp_dir().join(format!("taskmaster-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("taskmaster.yaml");
+        fs::write(&path, "programs:\n  cat:\n    cmd: /bin/cat\n    umask: 022\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let conf = Config::from_file(&path).unwrap();
+        let listener = UnixListener::bind(dir.join("taskmaster.socket")).unwrap();
+        let mut daemon = Daemon::new(conf, listener, path.clone(), ConfigFormat::Yaml);
+        daemon.gen_proc_list();
+        fs::write(&path, "programs:\n  cat:\n    cmd: /bin/cat\n    umask: 9\n").unwrap();
+        assert!(reload(&mut daemon, false).starts_with("ERROR (invalid config)"));
+        assert_eq!(daemon.conf.programs["cat"].umask, 0o22);
+        assert_eq!(daemon.proc_list["cat"].conf.umask, 0o22);
+        assert!(daemon.last_good.is_none());
+        fs::write(&path, "programs:\n  cat:\n    cmd: /bin/cat\n    umask: 022\n  job:\n    cmd: /nonexistent\n    schedule: \"0 0 1 1 *\"\n").unwrap();
+        assert!(reload(&mut daemon, false).starts_with("job: added\n"));
+        assert_eq!(daemon.proc_list["job"].status, ProcessStatus::Stopped);
+        assert!(!daemon.proc_list["job"].start_pending);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
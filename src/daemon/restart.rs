@@ -1,3 +1,64 @@
-use crate::cfg::Config;
+use crate::cfg::RestartPolicy;
 
-fn restart(command: Vec<&str>, conf: &Config) -> () {}
+use super::{start, stop, Daemon, ProcessInfo, ProcessStatus};
+
+/// Stop the programs and start them again once they are stopped.
+pub fn restart(args: Vec<&str>, daemon: &mut Daemon) -> String {
+    let names: Vec<String> = if args.is_empty() {
+        daemon.conf.start_order()
+    } else {
+        args.iter().map(|s| s.to_string()).collect()
+    };
+    let mut response = String::new();
+    for name in names {
+        if !daemon.proc_list.contains_key(&name) {
+            response += &format!("{}: ERROR (no such process)\n", name);
+            continue;
+        }
+        response += &stop::stop(vec![&name], daemon);
+        daemon.proc_list.get_mut(&name).unwrap().start_pending = true;
+    }
+    response
+}
+
+fn should_restart(proc: &ProcessInfo) -> bool {
+    let unhealthy = match &proc.conf.healthcheck {
+        Some(hc) => proc.health.failures >= hc.retries,
+        None => false,
+    } || matches!(&proc.notify, Some(n) if n.watchdog_expired);
+    match proc.conf.autorestart {
+        RestartPolicy::Always => true,
+        RestartPolicy::Never => false,
+        RestartPolicy::Unexpected => unhealthy || match proc.exit_status.and_then(|s| s.code()) {
+            Some(code) => !proc.conf.exitcodes.contains(&i64::from(code)),
+            None => true,
+        },
+    }
+}
+
+/// Start an exited program again if its restart policy asks for it, and a
+/// program that exited before it was RUNNING up to `startretries` times in
+/// a row, after which it is FATAL.
+pub fn autorestart(proc: &mut ProcessInfo) {
+    let restart = match proc.status {
+        ProcessStatus::Running => {
+            proc.retries = 0;
+            false
+        }
+        ProcessStatus::Backoff if proc.retries >= proc.conf.startretries => {
+            proc.status = ProcessStatus::Fatal;
+            println!("daemon: {}: gave up after {} retries", proc.conf.name, proc.retries);
+            false
+        }
+        ProcessStatus::Backoff => {
+            proc.retries += 1;
+            true
+        }
+        ProcessStatus::Exited => proc.conf.schedule.is_none() && should_restart(proc),
+        _ => false,
+    };
+    if restart {
+        let name = proc.conf.name.clone();
+        print!("daemon: {}", start::start_program(name, proc));
+    }
+}
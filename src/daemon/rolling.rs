@@ -0,0 +1,179 @@
+use super::{restart, select, Daemon, ProcessInfo, ProcessStatus};
+
+/// Restart of processes in batches, each batch waiting for the previous one
+/// to be back up.
+pub struct Rollout {
+    queue: Vec<String>,
+    batch: Vec<String>,
+    batch_size: usize,
+    wait_ready: bool,
+}
+
+enum BatchState {
+    Done,
+    Pending,
+    Failed(String),
+}
+
+impl Rollout {
+    pub fn new(names: Vec<String>, batch_size: usize, wait_ready: bool) -> Rollout {
+        Rollout {
+            queue: names,
+            batch: Vec::new(),
+            batch_size: batch_size.max(1),
+            wait_ready,
+        }
+    }
+
+    /// A process is up once RUNNING, and with `wait_ready` once its health
+    /// check passed too.
+    fn is_up(&self, proc: &ProcessInfo) -> bool {
+        proc.status == ProcessStatus::Running
+            && (!self.wait_ready
+                || proc.conf.healthcheck.is_none()
+                || matches!(proc.health.last_result, Some(Ok(()))))
+    }
+
+    fn batch_state(&self, daemon: &Daemon) -> BatchState {
+        let mut state = BatchState::Done;
+        for name in &self.batch {
+            let proc = match daemon.proc_list.get(name) {
+                Some(p) => p,
+                None => continue,
+            };
+            if proc.start_pending || proc.stop_pending || proc.status == ProcessStatus::Stopping {
+                state = BatchState::Pending;
+            } else if self.is_up(proc) {
+                continue;
+            } else if proc.is_alive() {
+                state = BatchState::Pending;
+            } else {
+                return BatchState::Failed(format!("{} is {}", name, proc.status));
+            }
+        }
+        state
+    }
+}
+
+/// Move every rollout to its next batch once the current one is up, and
+/// abort the ones whose batch failed.
+pub fn advance(daemon: &mut Daemon) -> String {
+    let mut response = String::new();
+    let mut rollouts = std::mem::take(&mut daemon.rollouts);
+    if daemon.shutting_down {
+        return response;
+    }
+    rollouts.retain_mut(|r| match r.batch_state(daemon) {
+        BatchState::Pending => true,
+        BatchState::Failed(reason) => {
+            response += &format!("rolling restart aborted: {}\n", reason);
+            false
+        }
+        BatchState::Done if r.queue.is_empty() => {
+            response += "rolling restart done\n";
+            false
+        }
+        BatchState::Done => {
+            let n = r.batch_size.min(r.queue.len());
+            r.batch = r.queue.drain(..n).collect();
+            response += &restart::restart(r.batch.iter().map(String::as_str).collect(), daemon);
+            true
+        }
+    });
+    daemon.rollouts.append(&mut rollouts);
+    response
+}
+
+/// `restart --rolling [--batch N] [--wait-ready] [process...]`
+pub fn rolling(args: &[&str], daemon: &mut Daemon) -> String {
+    let mut batch_size = 1;
+    let mut wait_ready = false;
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        match *arg {
+            "--batch" => match args.get(i + 1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if n > 0 => {
+                    batch_size = n;
+                    i += 1;
+                }
+                _ => return "ERROR (--batch expects a positive number)\n".to_string(),
+            },
+            "--wait-ready" => wait_ready = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    let selectors = &args[i..];
+    let (names, errors) = select::select(selectors, daemon);
+    if !selectors.is_empty() && names.is_empty() {
+        return errors;
+    }
+    let names = if selectors.is_empty() { daemon.conf.start_order() } else { names };
+    let response = format!(
+        "{}rolling restart of {} processes in batches of {}\n",
+        errors,
+        names.len(),
+        batch_size
+    );
+    daemon.rollouts.push(Rollout::new(names, batch_size, wait_ready));
+    response + &advance(daemon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advance, BatchState, Rollout};
+    use crate::cfg::{Config, ConfigFormat};
+    use crate::daemon::{start, Daemon, ProcessStatus};
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    fn daemon(name: &str) -> (Daemon, std::path::PathBuf) {
+        let c = Config::from_str("
+programs:
+  web:
+    cmd: /nonexistent
+    numprocs: 2
+    healthcheck:
+      tcp: 8080").unwrap();
+        let socket = std::env::temp_dir().join(format!("taskmaster-{}-{}.socket", name, std::process::id()));
+        let mut daemon = Daemon::new(c, UnixListener::bind(&socket).unwrap(), String::new(), ConfigFormat::Yaml);
+        daemon.gen_proc_list();
+        (daemon, socket)
+    }
+
+    #[test]
+    fn batch_state() {
+        let (mut daemon, socket) = daemon("rolling-batch");
+        let mut rollout = Rollout::new(Vec::new(), 1, false);
+        rollout.batch = vec!["web0".to_string()];
+        let web = daemon.proc_list.get_mut("web0").unwrap();
+        // a crash-looping process never gets past STARTING
+        web.status = ProcessStatus::Starting;
+        assert!(matches!(rollout.batch_state(&daemon), BatchState::Pending));
+        daemon.proc_list.get_mut("web0").unwrap().status = ProcessStatus::Running;
+        assert!(matches!(rollout.batch_state(&daemon), BatchState::Done));
+        rollout.wait_ready = true;
+        assert!(matches!(rollout.batch_state(&daemon), BatchState::Pending));
+        daemon.proc_list.get_mut("web0").unwrap().health.last_result = Some(Ok(()));
+        assert!(matches!(rollout.batch_state(&daemon), BatchState::Done));
+        daemon.proc_list.get_mut("web0").unwrap().status = ProcessStatus::Fatal;
+        assert!(matches!(rollout.batch_state(&daemon), BatchState::Failed(r) if r == "web0 is FATAL"));
+        fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    fn advance_aborted() {
+        let (mut daemon, socket) = daemon("rolling-advance");
+        daemon.rollouts.push(Rollout::new(vec!["web0".to_string(), "web1".to_string()], 1, false));
+        assert_eq!(advance(&mut daemon), "web0: not running
+");
+        assert_eq!(daemon.rollouts[0].batch, ["web0"]);
+        assert_eq!(daemon.rollouts[0].queue, ["web1"]);
+        // the first batch could not be started
+        assert!(start::start_pending(&mut daemon).starts_with("web0: not started"));
+        assert!(advance(&mut daemon).starts_with("rolling restart aborted: web0 is "));
+        assert!(daemon.rollouts.is_empty());
+        assert!(!daemon.proc_list["web1"].start_pending);
+        fs::remove_file(socket).unwrap();
+    }
+}
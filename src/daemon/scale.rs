@@ -0,0 +1,115 @@
+use super::{start, stop, Daemon, ProcessInfo};
+
+/// `numprocs` override of a program.
+pub struct Scale {
+    /// Value of the config when the override was set.
+    pub from: i64,
+    pub to: i64,
+}
+
+/// Bring the process list in line with the instances of a program after it
+/// was scaled, renaming the kept instances, stopping the removed ones and
+/// starting the new ones.
+pub fn apply(daemon: &mut Daemon, old: &[String], new: &[String]) -> String {
+    let mut response = String::new();
+    let mut added = Vec::new();
+    for i in 0..old.len().max(new.len()) {
+        match (old.get(i), new.get(i)) {
+            (Some(o), Some(n)) => {
+                if let Some(mut proc) = daemon.proc_list.remove(o) {
+                    proc.conf = daemon.conf.programs[n].clone();
+                    daemon.proc_list.insert(n.clone(), proc);
+                }
+            }
+            (Some(o), None) => {
+                if let Some(proc) = daemon.proc_list.get_mut(o) {
+                    if proc.has_processes() {
+                        response += &stop::stop_program(o.clone(), proc);
+                    }
+                    proc.start_pending = false;
+                    proc.remove_pending = true;
+                }
+            }
+            // an instance removed by a previous scale may still be stopping:
+            // keep it and start it again once it is stopped
+            (None, Some(n)) => match daemon.proc_list.get_mut(n) {
+                Some(proc) if proc.is_alive() => {
+                    proc.conf = daemon.conf.programs[n].clone();
+                    proc.remove_pending = false;
+                    proc.start_pending = true;
+                }
+                _ => {
+                    daemon
+                        .proc_list
+                        .insert(n.clone(), ProcessInfo::new(daemon.conf.programs[n].clone()));
+                    added.push(n.as_str());
+                }
+            },
+            (None, None) => (),
+        }
+    }
+    // the dependencies on the scaled program were renamed in the config
+    for (name, proc) in daemon.proc_list.iter_mut() {
+        if let Some(conf) = daemon.conf.programs.get(name) {
+            proc.conf.depends_on = conf.depends_on.clone();
+        }
+    }
+    if !added.is_empty() {
+        response += &start::start(added, daemon);
+    }
+    response
+}
+
+pub fn scale(args: Vec<&str>, daemon: &mut Daemon) -> String {
+    let (program, numprocs) = match args.as_slice() {
+        [p, n] => match n.parse::<i64>() {
+            Ok(n) => (p.to_string(), n),
+            Err(_) => return format!("{}: ERROR (invalid number of processes)\n", n),
+        },
+        _ => return "ERROR (usage: scale <program> <numprocs>)\n".to_string(),
+    };
+    let from = match (daemon.scales.get(&program), daemon.conf.groups.get(&program)) {
+        (Some(s), _) => s.from,
+        (None, Some(m)) if !m.is_empty() => daemon.conf.programs[&m[0]].numprocs,
+        _ => 0,
+    };
+    let (old, new) = match daemon.conf.scale(&program, numprocs) {
+        Ok(n) => n,
+        Err(e) => return format!("{}: ERROR ({})\n", program, e),
+    };
+    if numprocs == from {
+        daemon.scales.remove(&program);
+    } else {
+        daemon.scales.insert(program, Scale { from, to: numprocs });
+    }
+    apply(daemon, &old, &new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scale;
+    use crate::cfg::{Config, ConfigFormat};
+    use crate::daemon::{start, Daemon};
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn scale_dependency() {
+        let c = Config::from_str("
+programs:
+  worker:
+    cmd: /nonexistent
+  web:
+    cmd: /nonexistent
+    depends_on: [worker]").unwrap();
+        let socket = std::env::temp_dir().join(format!("taskmaster-scale-{}.socket", std::process::id()));
+        let mut daemon = Daemon::new(c, UnixListener::bind(&socket).unwrap(), String::new(), ConfigFormat::Yaml);
+        daemon.gen_proc_list();
+        scale(vec!["worker", "3"], &mut daemon);
+        assert_eq!(daemon.proc_list["web"].conf.depends_on, ["worker0", "worker1", "worker2"]);
+        assert!(start::start(vec!["web"], &mut daemon).contains("web: not started (dependency worker0 is"));
+        scale(vec!["worker", "2"], &mut daemon);
+        assert_eq!(daemon.proc_list["web"].conf.depends_on, ["worker0", "worker1"]);
+        fs::remove_file(socket).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Local};
+
+use crate::cfg::OverlapPolicy;
+
+use super::{start, Daemon, ProcessInfo};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Runs of a scheduled program.
+#[derive(Default)]
+pub struct Job {
+    pub last_run: Option<DateTime<Local>>,
+    pub next_run: Option<DateTime<Local>>,
+    /// Runs that were due while the previous one was still going.
+    pub queued: u32,
+}
+
+impl Job {
+    pub fn status_str(&self, proc: &ProcessInfo) -> String {
+        let last = match self.last_run {
+            Some(t) => t.format(TIME_FORMAT).to_string(),
+            None => "never".to_string(),
+        };
+        let code = match proc.exit_status.and_then(|s| s.code()) {
+            Some(c) if !proc.is_alive() && self.last_run.is_some() => format!(", exit code {}", c),
+            _ => String::new(),
+        };
+        let next = match self.next_run {
+            Some(t) => t.format(TIME_FORMAT).to_string(),
+            None => "none".to_string(),
+        };
+        let queued = match self.queued {
+            0 => String::new(),
+            n => format!(", {} queued", n),
+        };
+        format!(", last run {}{}, next run {}{}", last, code, next, queued)
+    }
+}
+
+/// Start the scheduled programs that are due. A run due while the previous
+/// one is still going is skipped or queued as `overlap` says.
+pub fn run_due(daemon: &mut Daemon) -> String {
+    if daemon.shutting_down {
+        return String::new();
+    }
+    let now = Local::now();
+    let mut response = String::new();
+    let mut due = Vec::new();
+    for (name, proc) in daemon.proc_list.iter_mut() {
+        let schedule = match &proc.conf.schedule {
+            Some(s) if !proc.remove_pending => s,
+            _ => continue,
+        };
+        let busy = proc.is_alive() || proc.start_pending;
+        match proc.job.next_run {
+            None => proc.job.next_run = schedule.next_after(&now),
+            Some(t) if t <= now => {
+                proc.job.next_run = schedule.next_after(&now);
+                if !busy {
+                    due.push(name.clone());
+                } else if proc.conf.overlap == OverlapPolicy::Queue {
+                    proc.job.queued += 1;
+                    response += &format!("{}: run queued, previous run still going\n", name);
+                } else {
+                    response += &format!("{}: run skipped, previous run still going\n", name);
+                }
+            }
+            Some(_) => (),
+        }
+        if proc.job.queued > 0 && !busy && !due.contains(name) {
+            proc.job.queued -= 1;
+            due.push(name.clone());
+        }
+    }
+    // the run is recorded once the program is actually started
+    for name in &due {
+        response += &start::request_start(name, daemon);
+    }
+    if !due.is_empty() {
+        response += &start::start_pending(daemon);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_due;
+    use crate::cfg::{Config, ConfigFormat};
+    use crate::daemon::Daemon;
+    use chrono::{Duration, Local};
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn run_not_started() {
+        let c = Config::from_str("
+programs:
+  job:
+    cmd: /nonexistent
+    schedule: \"* * * * *\"").unwrap();
+        let socket = std::env::temp_dir().join(format!("taskmaster-schedule-{}.socket", std::process::id()));
+        let mut daemon = Daemon::new(c, UnixListener::bind(&socket).unwrap(), String::new(), ConfigFormat::Yaml);
+        daemon.gen_proc_list();
+        daemon.proc_list.get_mut("job").unwrap().job.next_run = Some(Local::now() - Duration::seconds(1));
+        assert!(run_due(&mut daemon).contains("job: not started"));
+        assert_eq!(daemon.proc_list["job"].job.last_run, None);
+        assert!(daemon.proc_list["job"].job.next_run.is_some_and(|t| t > Local::now()));
+        fs::remove_file(socket).unwrap();
+    }
+}
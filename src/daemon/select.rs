@@ -0,0 +1,143 @@
+use glob::Pattern;
+use regex::Regex;
+
+use super::Daemon;
+
+enum Selector {
+    Name(String),
+    Group(String),
+    Glob(Pattern),
+    Regex(Regex),
+    Label(String, Option<String>),
+}
+
+impl Selector {
+    fn parse(arg: &str) -> Option<Selector> {
+        if let Some(group) = arg.strip_suffix(":*") {
+            return Some(Selector::Group(group.to_string()));
+        }
+        if let Some(label) = arg.strip_prefix("label=") {
+            return Some(match label.split_once(':') {
+                Some((k, v)) => Selector::Label(k.to_string(), Some(v.to_string())),
+                None => Selector::Label(label.to_string(), None),
+            });
+        }
+        if let Some(re) = arg.strip_prefix('/').and_then(|a| a.strip_suffix('/')) {
+            // a pattern has to match the whole process name
+            return Regex::new(&format!("^(?:{})$", re)).ok().map(Selector::Regex);
+        }
+        if arg.contains(['*', '?', '[']) {
+            return Pattern::new(arg).ok().map(Selector::Glob);
+        }
+        Some(Selector::Name(arg.to_string()))
+    }
+
+    fn matches(&self, name: &str, daemon: &Daemon) -> bool {
+        match self {
+            Selector::Name(n) => n == name,
+            Selector::Group(g) => matches!(daemon.conf.groups.get(g), Some(m) if m.iter().any(|m| m == name)),
+            Selector::Glob(p) => p.matches(name),
+            Selector::Regex(r) => r.is_match(name),
+            Selector::Label(k, v) => match daemon.proc_list[name].conf.labels.get(k) {
+                Some(value) => v.as_ref().is_none_or(|v| v == value),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Expand the process selectors of a command into process names, in start
+/// order. Selectors are exact names, `group:*`, globs, `/regex/` and
+/// `label=key[:value]`. Also returns the errors for the selectors matching
+/// nothing.
+pub fn select(args: &[&str], daemon: &Daemon) -> (Vec<String>, String) {
+    let candidates: Vec<String> = daemon
+        .conf
+        .start_order()
+        .into_iter()
+        .filter(|n| daemon.proc_list.contains_key(n))
+        .collect();
+    let mut names: Vec<String> = Vec::new();
+    let mut errors = String::new();
+    for arg in args {
+        let selector = match Selector::parse(arg) {
+            Some(s) => s,
+            None => {
+                errors += &format!("{}: ERROR (invalid pattern)\n", arg);
+                continue;
+            }
+        };
+        let matched: Vec<&String> = candidates
+            .iter()
+            .filter(|n| selector.matches(n, daemon))
+            .collect();
+        if matched.is_empty() {
+            errors += &format!("{}: ERROR (no such process)\n", arg);
+        }
+        for name in matched {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    (names, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, Selector};
+    use crate::cfg::{Config, ConfigFormat};
+    use crate::daemon::Daemon;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn parse_selectors() {
+        assert!(matches!(Selector::parse("worker0"), Some(Selector::Name(n)) if n == "worker0"));
+        assert!(matches!(Selector::parse("web:*"), Some(Selector::Group(g)) if g == "web"));
+        assert!(matches!(Selector::parse("worker?"), Some(Selector::Glob(_))));
+        assert!(matches!(Selector::parse("worker[12]"), Some(Selector::Glob(_))));
+        assert!(matches!(Selector::parse("/work.*/"), Some(Selector::Regex(_))));
+        assert!(matches!(Selector::parse("label=tier"), Some(Selector::Label(k, None)) if k == "tier"));
+        assert!(matches!(
+            Selector::parse("label=tier:back"),
+            Some(Selector::Label(k, Some(v))) if k == "tier" && v == "back"
+        ));
+        assert!(Selector::parse("/work(/").is_none());
+        assert!(Selector::parse("worker[").is_none());
+    }
+
+    #[test]
+    fn select_processes() {
+        let c = Config::from_str("
+programs:
+  nginx:
+    cmd: /bin/cat
+    labels:
+      tier: front
+  worker:
+    cmd: /bin/cat
+    numprocs: 3
+    labels:
+      tier: back
+groups:
+  web: [nginx, worker]").unwrap();
+        let socket = std::env::temp_dir().join(format!("taskmaster-select-{}.socket", std::process::id()));
+        let mut daemon = Daemon::new(c, UnixListener::bind(&socket).unwrap(), String::new(), ConfigFormat::Yaml);
+        daemon.gen_proc_list();
+        assert_eq!(select(&["worker1"], &daemon), (vec!["worker1".to_string()], String::new()));
+        assert_eq!(select(&["web:*"], &daemon).0, ["nginx", "worker0", "worker1", "worker2"]);
+        assert_eq!(select(&["worker:*", "worker1"], &daemon).0, ["worker0", "worker1", "worker2"]);
+        assert_eq!(select(&["worker[12]"], &daemon).0, ["worker1", "worker2"]);
+        // a regex is anchored to the whole name
+        assert_eq!(select(&["/work/"], &daemon).1, "/work/: ERROR (no such process)\n");
+        assert_eq!(select(&["/(nginx|worker0)/"], &daemon).0, ["nginx", "worker0"]);
+        assert_eq!(select(&["label=tier"], &daemon).0, ["nginx", "worker0", "worker1", "worker2"]);
+        assert_eq!(select(&["label=tier:front"], &daemon).0, ["nginx"]);
+        assert_eq!(
+            select(&["nginx", "ngin", "/ngin(/"], &daemon),
+            (vec!["nginx".to_string()], "ngin: ERROR (no such process)\n/ngin(/: ERROR (invalid pattern)\n".to_string())
+        );
+        fs::remove_file(socket).unwrap();
+    }
+}
@@ -1,5 +1,15 @@
-use std::process::exit;
+use std::process;
 
-pub fn shutdown() -> String {
-    exit(0);
+use super::{stop, Daemon};
+
+/// Stop every program, dependents first. The daemon exits once they are all
+/// stopped.
+pub fn shutdown(daemon: &mut Daemon) -> String {
+    daemon.shutting_down = true;
+    stop::stop(Vec::new(), daemon) + "shutting down\n"
+}
+
+pub fn exit() {
+    println!("daemon: all programs stopped, exiting");
+    process::exit(0);
 }
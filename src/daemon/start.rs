@@ -1,12 +1,22 @@
 extern crate libc;
-use super::{ProcessInfo, ProcessList, ProcessStatus, status, Daemon};
-use crate::cfg::{Config, ProgramConfig};
+use super::{health, notify, pidfile, ProcessInfo, ProcessStatus, Daemon};
+use crate::cfg::{ProgramConfig, ReadyPolicy};
+use crate::common::comm::SOCKET_PATH;
+use chrono::Local;
+use std::collections::HashMap;
+use std::env;
 use std::ffi::OsStr;
 use std::io;
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
 use std::time::Instant;
 
-fn exec_cmd<I, S>(cmd_name: &str, args: I, prog_conf: &ProgramConfig) -> io::Result<Child>
+fn exec_cmd<I, S>(
+    cmd_name: &str,
+    args: I,
+    prog_conf: &ProgramConfig,
+    env: &HashMap<String, String>,
+) -> io::Result<Child>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
@@ -14,9 +24,13 @@ where
     let mode = unsafe { libc::umask(prog_conf.umask) };
     let mut cmd = Command::new(cmd_name);
     cmd.args(args)
+        .envs(&prog_conf.env)
+        .envs(env)
         .stdout(prog_conf.open_stdout())
         .stderr(prog_conf.open_stderr())
-        .stdin(Stdio::null());
+        .stdin(Stdio::null())
+        // lead a new process group so orphaned descendants can be traced back
+        .process_group(0);
     if let Some(wd) = &prog_conf.workingdir {
         cmd.current_dir(wd);
     };
@@ -26,19 +40,56 @@ where
     cmd.spawn()
 }
 
-fn start_program(name: String, proc: &mut ProcessInfo) -> String {
+fn server_url() -> String {
+    let path = env::current_dir().unwrap_or_default().join(SOCKET_PATH);
+    format!("unix://{}", path.to_string_lossy())
+}
+
+/// Variables telling a program which instance it is and how to reach the
+/// daemon.
+fn metadata_env(proc: &ProcessInfo) -> HashMap<String, String> {
+    HashMap::from([
+        ("TASKMASTER_PROGRAM".to_string(), proc.conf.group.clone()),
+        ("TASKMASTER_PROCESS_NUM".to_string(), proc.conf.process_num.to_string()),
+        ("TASKMASTER_GROUP".to_string(), proc.conf.group_name.clone()),
+        ("TASKMASTER_SERVER_URL".to_string(), server_url()),
+        ("TASKMASTER_START_COUNT".to_string(), (proc.start_nb + 1).to_string()),
+    ])
+}
+
+pub fn start_program(name: String, proc: &mut ProcessInfo) -> String {
     let mut argv = proc.conf.cmd.split_whitespace();
-    let cmd_name = match argv.nth(0) {
-        Some(n) => n,
-        None => "",
-    };
-    let args = argv.clone().skip(1);
-    let cmd = exec_cmd(cmd_name, args, &proc.conf);
+    let cmd_name = argv.next().unwrap_or_default();
+    let args = argv;
+    pidfile::clear(proc);
+    let mut env = metadata_env(proc);
+    proc.notify = None;
+    if proc.conf.ready == ReadyPolicy::Notify {
+        match notify::Notify::bind(&name) {
+            Ok(n) => {
+                env.extend(n.env(proc.conf.watchdog));
+                proc.notify = Some(n);
+            }
+            Err(e) => {
+                proc.status = ProcessStatus::Stopped;
+                return format!("{}: not started ({})\n", name, e);
+            }
+        }
+    }
+    let cmd = exec_cmd(cmd_name, args, &proc.conf, &env);
     if cmd.is_ok() {
         proc.child = cmd.ok();
+        proc.exit_status = None;
+        proc.exit_time = None;
+        proc.main_pid = None;
+        proc.stop_time = None;
+        proc.health = health::Health::default();
         proc.status = ProcessStatus::Starting;
         proc.start_time = Some(Instant::now());
         proc.start_nb += 1;
+        if proc.conf.schedule.is_some() {
+            proc.job.last_run = Some(Local::now());
+        }
         format!("{}: started\n", name)
     } else {
         proc.status = ProcessStatus::Stopped;
@@ -46,20 +97,125 @@ fn start_program(name: String, proc: &mut ProcessInfo) -> String {
     }
 }
 
-//TODO: check program status before starting it
-pub fn start(line: Vec<&str>, daemon: &mut Daemon) -> String {
-    let mut response: String = String::new();
-    if line.len() > 0 {
-        for program in line {
-            //TODO:check get_mut return
-            if daemon.proc_list.contains_key(program) {
-                response += &start_program(program.to_string(), &mut daemon.proc_list.get_mut(program).unwrap());
+/// Queue the start of a program and of the dependencies it is waiting for.
+pub fn request_start(name: &str, daemon: &mut Daemon) -> String {
+    let proc = &daemon.proc_list[name];
+    if proc.is_alive() || proc.start_pending {
+        return format!("{}: ERROR (already started)\n", name);
+    }
+    let mut response = String::new();
+    for dep in proc.conf.depends_on.clone() {
+        match daemon.proc_list.get(&dep) {
+            Some(d) if !d.is_alive() && !d.start_pending => response += &request_start(&dep, daemon),
+            // reported by `start_pending`
+            _ => (),
+        }
+    }
+    let proc = daemon.proc_list.get_mut(name).unwrap();
+    proc.retries = 0;
+    proc.start_pending = true;
+    proc.stop_pending = false;
+    response
+}
+
+enum Deps {
+    Ready,
+    Waiting,
+    Failed(String),
+}
+
+fn deps_state(proc: &ProcessInfo, daemon: &Daemon) -> Deps {
+    for dep in &proc.conf.depends_on {
+        let d = match daemon.proc_list.get(dep) {
+            Some(d) => d,
+            None => return Deps::Failed(dep.to_string()),
+        };
+        if d.status == ProcessStatus::Running {
+            continue;
+        }
+        if d.is_alive() || d.start_pending {
+            return Deps::Waiting;
+        }
+        return Deps::Failed(dep.to_string());
+    }
+    Deps::Ready
+}
+
+/// Start the queued programs whose dependencies are all RUNNING.
+pub fn start_pending(daemon: &mut Daemon) -> String {
+    let mut response = String::new();
+    for name in daemon.conf.start_order() {
+        let proc = match daemon.proc_list.get(&name) {
+            // a restarted program is started again once it is stopped
+            Some(p) if p.start_pending && !p.is_alive() && !p.stop_pending => p,
+            _ => continue,
+        };
+        let state = deps_state(proc, daemon);
+        let proc = daemon.proc_list.get_mut(&name).unwrap();
+        match state {
+            Deps::Ready => {
+                proc.start_pending = false;
+                response += &start_program(name, proc);
             }
+            Deps::Failed(dep) => {
+                proc.start_pending = false;
+                response += &match daemon.proc_list.get(&dep) {
+                    Some(d) => format!("{}: not started (dependency {} is {})\n", name, dep, d.status),
+                    None => format!("{}: not started (dependency {} not found)\n", name, dep),
+                };
+            }
+            Deps::Waiting => (),
         }
+    }
+    response
+}
+
+pub fn start(line: Vec<&str>, daemon: &mut Daemon) -> String {
+    let mut response: String = String::new();
+    let names = if !line.is_empty() {
+        line.iter().map(|s| s.to_string()).collect()
     } else {
-        for (program, proc_info) in &mut daemon.proc_list {
-            response += &start_program(program.to_string(), proc_info);
+        daemon.conf.start_order()
+    };
+    for program in names {
+        if daemon.proc_list.contains_key(&program) {
+            response += &request_start(&program, daemon);
+        } else {
+            response += &format!("{}: ERROR (no such process)\n", program);
         }
     }
-    response
+    let started = start_pending(daemon);
+    for (name, proc) in &daemon.proc_list {
+        if proc.start_pending {
+            response += &format!("{}: waiting for dependencies\n", name);
+        }
+    }
+    response + &started
+}
+
+#[cfg(test)]
+mod tests {
+    use super::metadata_env;
+    use crate::cfg::Config;
+    use crate::daemon::ProcessInfo;
+
+    #[test]
+    fn instance_env() {
+        let c = Config::from_str("
+programs:
+  worker:
+    cmd: /bin/cat
+    numprocs: 2
+  web:
+    cmd: /bin/cat
+groups:
+  backend: [worker]").unwrap();
+        let env = metadata_env(&ProcessInfo::new(c.programs["worker1"].clone()));
+        assert_eq!(env["TASKMASTER_PROGRAM"], "worker");
+        assert_eq!(env["TASKMASTER_PROCESS_NUM"], "1");
+        assert_eq!(env["TASKMASTER_GROUP"], "backend");
+        assert_eq!(env["TASKMASTER_START_COUNT"], "1");
+        let env = metadata_env(&ProcessInfo::new(c.programs["web"].clone()));
+        assert_eq!(env["TASKMASTER_GROUP"], "web");
+    }
 }
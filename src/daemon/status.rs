@@ -1,27 +1,29 @@
-use std::process::{Child, ExitStatus};
 use std::time::{Duration, Instant};
 
-use super::{Daemon, ProcessInfo, ProcessStatus};
+use nix::sys::signal;
 
-fn check_time(start: Instant, duration: i64) -> bool {
-    if Instant::now() - start > Duration::from_secs(duration.unsigned_abs()) {
-        true
-    } else {
-        false
-    }
+use super::{pidfile, Daemon, ProcessInfo, ProcessStatus};
+use crate::cfg::{ProcessType, ReadyPolicy};
+
+pub fn check_time(start: Instant, duration: i64) -> bool {
+    Instant::now() - start > Duration::from_secs(duration.unsigned_abs())
 }
 
-fn get_exit_code(proc: &mut ProcessInfo) -> () {
-    match &mut proc.child {
-        Some(c) => match c.try_wait() {
+fn get_exit_code(proc: &mut ProcessInfo) {
+    if proc.main_pid.is_some() {
+        pidfile::check_alive(proc);
+        return;
+    }
+    if let Some(c) = &mut proc.child {
+        match c.try_wait() {
             Ok(Some(status)) => {
                 proc.exit_status = Some(status);
                 proc.exit_time = Some(Instant::now());
             },
             Ok(None) => proc.exit_status = None,
+            // already reaped by the daemon, status was recorded then
             Err(_) => (),
-        },
-        None => (),
+        }
     }
 }
 
@@ -31,27 +33,65 @@ fn get_next_state(
     ok_none: ProcessStatus,
 ) -> ProcessStatus {
     get_exit_code(proc);
-    match proc.exit_status {
+    match proc.exit_time {
         Some(_) => ok_some,
         None => ok_none,
     }
 }
 
-//TODO: check if process starting or stoping should change state based on time
-fn check_state(proc: &mut ProcessInfo) -> ProcessStatus {
+fn is_started(proc: &ProcessInfo) -> bool {
+    match (&proc.conf.ready, &proc.notify, proc.start_time) {
+        (ReadyPolicy::Notify, Some(n), _) => n.ready,
+        (ReadyPolicy::Starttime, _, Some(t)) => check_time(t, proc.conf.starttime),
+        _ => false,
+    }
+}
+
+/// Whether a `ready: notify` program did not send READY=1 within
+/// `starttime`.
+fn notify_timed_out(proc: &ProcessInfo) -> bool {
+    matches!(
+        (&proc.conf.ready, &proc.notify, proc.start_time),
+        (ReadyPolicy::Notify, Some(n), Some(t)) if !n.ready && check_time(t, proc.conf.starttime)
+    )
+}
+
+pub fn check_state(proc: &mut ProcessInfo) -> ProcessStatus {
     match proc.status {
         ProcessStatus::Starting => {
-            get_next_state(proc, ProcessStatus::Backoff, ProcessStatus::Starting)
+            if proc.conf.proc_type == ProcessType::Forking && proc.main_pid.is_none() {
+                return pidfile::check_launcher(proc);
+            }
+            // a scheduled program is allowed to exit early
+            let exited = match proc.conf.schedule {
+                Some(_) => ProcessStatus::Exited,
+                None => ProcessStatus::Backoff,
+            };
+            match get_next_state(proc, exited, ProcessStatus::Starting) {
+                ProcessStatus::Starting if is_started(proc) => ProcessStatus::Running,
+                ProcessStatus::Starting if notify_timed_out(proc) => {
+                    if let Some(pid) = proc.live_pid() {
+                        let _ = signal::kill(pid, signal::SIGKILL);
+                    }
+                    println!("daemon: {}: not ready after {}s", proc.conf.name, proc.conf.starttime);
+                    ProcessStatus::Backoff
+                }
+                next => next,
+            }
         }
         ProcessStatus::Running => {
             get_next_state(proc, ProcessStatus::Exited, ProcessStatus::Running)
         }
+        ProcessStatus::Unhealthy => {
+            get_next_state(proc, ProcessStatus::Exited, ProcessStatus::Unhealthy)
+        }
         ProcessStatus::Stopping => {
-            get_next_state(proc, ProcessStatus::Stopped, ProcessStatus::Stopping)
+            match get_next_state(proc, ProcessStatus::Stopped, ProcessStatus::Stopping) {
+                ProcessStatus::Stopped if !proc.descendants.is_empty() => ProcessStatus::Stopping,
+                next => next,
+            }
         }
-        //TODO: exited: check if exitcode it ok and if program needs to restart
         ProcessStatus::Exited => ProcessStatus::Exited,
-        //TODO: backoff: check number of retries 
         ProcessStatus::Backoff => ProcessStatus::Backoff,
         ProcessStatus::Unknown => ProcessStatus::Unknown,
         ProcessStatus::Fatal => ProcessStatus::Fatal,
@@ -59,18 +99,47 @@ fn check_state(proc: &mut ProcessInfo) -> ProcessStatus {
     }
 }
 
+fn group_status_str(name: &str, members: &[String], daemon: &Daemon) -> String {
+    let running = members
+        .iter()
+        .filter(|m| daemon.proc_list[*m].status == ProcessStatus::Running)
+        .count();
+    format!("{:33} {}/{} RUNNING\n", name, running, members.len())
+}
+
+/// One line per group, for all groups or the given ones.
+pub fn groups_status(args: &[&str], daemon: &Daemon) -> String {
+    let mut response = String::new();
+    let mut names: Vec<&str> = if args.is_empty() {
+        daemon.conf.groups.keys().map(String::as_str).collect()
+    } else {
+        args.to_vec()
+    };
+    names.sort();
+    for name in names {
+        match daemon.conf.groups.get(name) {
+            Some(members) => response += &group_status_str(name, members, daemon),
+            None => response += &format!("group ({}) not found\n", name),
+        }
+    }
+    response
+}
+
 pub fn status(args: Vec<&str>, daemon: &Daemon) -> String {
     let mut response: String = String::new();
     if args.is_empty() {
-        for (_, info) in &daemon.proc_list {
+        for info in daemon.proc_list.values() {
             response += &info.status_str();
         }
+        if let Some(outcome) = &daemon.last_transaction {
+            response += &format!("last atomic reload: {}\n", outcome);
+        }
     } else {
         for prog in args {
             if daemon.proc_list.contains_key(prog) {
-                response += &(&daemon.proc_list[prog]).status_str();
+                response += &daemon.proc_list[prog].status_str();
             } else {
-                response += &format!("process ({}) not found\n", prog)
+                response += &format!("{}: ERROR (no such process)\n", prog)
             }
         }
     }
@@ -1,36 +1,132 @@
 use std::time::Instant;
 
-use nix::{sys::signal, unistd::Pid};
+use nix::sys::signal;
 
-use super::{Daemon, ProcessInfo, ProcessStatus};
+use super::{status, Daemon, ProcessInfo, ProcessStatus};
 
+/// Signal a program and its adopted descendants, which may outlive it when
+/// it double-forked. The program stays STOPPING until they are all reaped.
 #[allow(unused_must_use)]
-fn stop_program(name: String, proc: &mut ProcessInfo) -> String {
-    match proc.child.as_mut() {
-        Some(c) => {
-            signal::kill(Pid::from_raw(c.id() as i32), proc.conf.stopsignal);
-            proc.status = ProcessStatus::Stopping;
-            proc.stop_time = Some(Instant::now());
-            format!("{}: stopped\n", name)
+pub fn stop_program(name: String, proc: &mut ProcessInfo) -> String {
+    let pid = proc.live_pid();
+    if pid.is_none() && proc.descendants.is_empty() {
+        return format!("{}: not running\n", name);
+    }
+    if let Some(pid) = pid {
+        signal::kill(pid, proc.conf.stopsignal);
+    }
+    for pid in &proc.descendants {
+        signal::kill(*pid, proc.conf.stopsignal);
+    }
+    proc.status = ProcessStatus::Stopping;
+    proc.stop_time = Some(Instant::now());
+    format!("{}: stopped\n", name)
+}
+
+/// Kill a stopping program and its adopted descendants once `stoptime` has
+/// elapsed without them exiting.
+#[allow(unused_must_use)]
+pub fn kill_overdue(proc: &mut ProcessInfo) {
+    if !matches!(proc.status, ProcessStatus::Stopping | ProcessStatus::Unhealthy) {
+        return;
+    }
+    match proc.stop_time {
+        Some(t) if status::check_time(t, proc.conf.stoptime) => (),
+        _ => return,
+    }
+    if let Some(pid) = proc.live_pid() {
+        signal::kill(pid, signal::SIGKILL);
+    }
+    for pid in &proc.descendants {
+        signal::kill(*pid, signal::SIGKILL);
+    }
+}
+
+fn request_stop(name: &str, daemon: &mut Daemon) -> String {
+    let proc = daemon.proc_list.get_mut(name).unwrap();
+    if proc.start_pending {
+        proc.start_pending = false;
+        return format!("{}: stopped\n", name);
+    }
+    proc.stop_pending = true;
+    String::new()
+}
+
+fn is_waited_on(name: &str, daemon: &Daemon) -> bool {
+    daemon.proc_list.values().any(|p| {
+        p.conf.depends_on.iter().any(|d| d == name)
+            && (p.stop_pending || p.status == ProcessStatus::Stopping)
+    })
+}
+
+/// Stop the queued programs whose dependents are all stopped.
+pub fn stop_pending(daemon: &mut Daemon) -> String {
+    let mut response = String::new();
+    for name in daemon.conf.start_order().into_iter().rev() {
+        match daemon.proc_list.get(&name) {
+            Some(p) if p.stop_pending => (),
+            _ => continue,
+        }
+        if is_waited_on(&name, daemon) {
+            continue;
         }
-        None => format!("{}: not running\n", name),
+        let proc = daemon.proc_list.get_mut(&name).unwrap();
+        proc.stop_pending = false;
+        response += &stop_program(name, proc);
     }
+    response
 }
 
 pub fn stop(args: Vec<&str>, daemon: &mut Daemon) -> String {
     let mut response: String = String::new();
-    if args.len() > 0 {
+    if !args.is_empty() {
         for program in args {
             if daemon.proc_list.contains_key(program) {
-                response += &stop_program(program.to_string(), &mut daemon.proc_list.get_mut(program).unwrap());
+                response += &request_stop(program, daemon);
             } else {
-                response += &format!("{}: ERROR (no such process)", program)
+                response += &format!("{}: ERROR (no such process)\n", program)
             }
         }
     } else {
-        for (program, proc_info) in &mut daemon.proc_list {
-            response += &stop_program(program.to_string(), proc_info);
+        for program in daemon.conf.start_order() {
+            let proc = &daemon.proc_list[&program];
+            if proc.has_processes() || proc.start_pending {
+                response += &request_stop(&program, daemon);
+            }
         }
     }
-    response
+    let stopped = stop_pending(daemon);
+    for (name, proc) in &daemon.proc_list {
+        if proc.stop_pending {
+            response += &format!("{}: waiting for dependents\n", name);
+        }
+    }
+    response + &stopped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stop_program;
+    use crate::cfg::Config;
+    use crate::daemon::{ProcessInfo, ProcessStatus};
+    use nix::unistd::Pid;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn stop_descendants() {
+        let c = Config::from_str("
+programs:
+  launcher:
+    cmd: /bin/true").unwrap();
+        let mut proc = ProcessInfo::new(c.programs["launcher"].clone());
+        assert_eq!(stop_program("launcher".to_string(), &mut proc), "launcher: not running\n");
+        // the launcher exited, leaving a daemonized process behind
+        let mut orphan = Command::new("/bin/sleep").arg("10").spawn().unwrap();
+        proc.status = ProcessStatus::Exited;
+        proc.descendants.push(Pid::from_raw(orphan.id() as i32));
+        assert_eq!(stop_program("launcher".to_string(), &mut proc), "launcher: stopped\n");
+        assert_eq!(proc.status, ProcessStatus::Stopping);
+        assert_eq!(orphan.wait().unwrap().signal(), Some(libc::SIGTERM));
+    }
 }
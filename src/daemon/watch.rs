@@ -0,0 +1,112 @@
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use glob::Pattern;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use nix::unistd::close;
+
+use crate::cfg::Config;
+
+use super::{reload, Daemon};
+
+/// Time without changes to wait for before reloading, as editors write a
+/// file in several steps.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the directories of the config files, which editors often replace
+/// rather than write to.
+pub struct Watcher {
+    inotify: Inotify,
+    dirs: Vec<(WatchDescriptor, String)>,
+    /// Config files and include patterns.
+    patterns: Vec<Pattern>,
+    changed_at: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn new(conf: &Config) -> nix::Result<Watcher> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut watcher = Watcher {
+            inotify,
+            dirs: Vec::new(),
+            patterns: Vec::new(),
+            changed_at: None,
+        };
+        let files = conf.files.iter().map(|f| fs::canonicalize(f).map_or(f.clone(), |p| p.to_string_lossy().to_string()));
+        let files = files.map(|f| Pattern::escape(&f)).collect::<Vec<_>>();
+        for pattern in files.iter().chain(&conf.includes) {
+            let dir = match Path::new(pattern).parent() {
+                Some(d) => d.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if let Ok(p) = Pattern::new(pattern) {
+                watcher.patterns.push(p);
+            }
+            let literal = Pattern::escape(&dir) == dir;
+            if !literal || watcher.dirs.iter().any(|(_, d)| *d == dir) {
+                continue;
+            }
+            let flags = AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_MOVED_TO;
+            let wd = inotify.add_watch(dir.as_str(), flags)?;
+            watcher.dirs.push((wd, dir));
+        }
+        Ok(watcher)
+    }
+
+    /// Whether one of the watched files changed since the last call.
+    fn changed(&self) -> bool {
+        let events = self.inotify.read_events().unwrap_or_default();
+        events.iter().any(|e| {
+            let dir = self.dirs.iter().find(|(wd, _)| *wd == e.wd).map(|(_, d)| d);
+            match (dir, &e.name) {
+                (Some(dir), Some(name)) => {
+                    let path = Path::new(dir).join(name);
+                    self.patterns.iter().any(|p| p.matches_path(&path))
+                }
+                _ => false,
+            }
+        })
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = close(self.inotify.as_raw_fd());
+    }
+}
+
+/// Start or stop watching the config files as `watch_config` says, with
+/// the files of the current config.
+pub fn refresh(daemon: &mut Daemon) {
+    daemon.watcher = None;
+    if !daemon.conf.watch_config {
+        return;
+    }
+    match Watcher::new(&daemon.conf) {
+        Ok(w) => daemon.watcher = Some(w),
+        Err(e) => println!("daemon: failed to watch the config files: {}", e),
+    }
+}
+
+/// Reload the config once its files stopped changing. An invalid config is
+/// reported and left unapplied by the reload.
+pub fn check(daemon: &mut Daemon) -> String {
+    let watcher = match &mut daemon.watcher {
+        Some(w) => w,
+        None => return String::new(),
+    };
+    if watcher.changed() {
+        watcher.changed_at = Some(Instant::now());
+    }
+    match watcher.changed_at {
+        Some(t) if t.elapsed() >= DEBOUNCE => watcher.changed_at = None,
+        _ => return String::new(),
+    }
+    "config file changed, reloading\n".to_string() + &reload::reload(daemon, false)
+}
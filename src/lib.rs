@@ -1,3 +1,5 @@
 pub mod common;
 pub mod cfg;
 pub mod daemon;
+pub mod supervisord;
+pub mod systemd;
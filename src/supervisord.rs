@@ -0,0 +1,265 @@
+//! Conversion of supervisord configurations to taskmaster ones.
+
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter};
+
+use crate::cfg::Config;
+
+/// Options of a `[program:x]` section renamed in taskmaster.
+const RENAMED: [(&str, &str); 16] = [
+    ("command", "cmd"),
+    ("numprocs", "numprocs"),
+    ("numprocs_start", "numprocs_start"),
+    ("process_name", "process_name"),
+    ("umask", "umask"),
+    ("directory", "workingdir"),
+    ("autostart", "autostart"),
+    ("autorestart", "autorestart"),
+    ("exitcodes", "exitcodes"),
+    ("startretries", "startretries"),
+    ("startsecs", "starttime"),
+    ("stopsignal", "stopsignal"),
+    ("stopwaitsecs", "stoptime"),
+    ("stdout_logfile", "stdout"),
+    ("stderr_logfile", "stderr"),
+    ("priority", "priority"),
+];
+
+/// Section of an INI file with its options, in order.
+struct Section {
+    name: String,
+    options: Vec<(String, String)>,
+}
+
+/// Convert a supervisord configuration to taskmaster YAML, along with a
+/// warning for everything that could not be translated.
+pub fn import(ini: &str) -> (String, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut programs = Hash::new();
+    let mut groups = Hash::new();
+    for section in parse_ini(ini, &mut warnings) {
+        if let Some(name) = section.name.strip_prefix("program:") {
+            let program = import_program(&section, &mut warnings);
+            programs.insert(Yaml::String(name.to_string()), Yaml::Hash(program));
+        } else if let Some(name) = section.name.strip_prefix("group:") {
+            let members = import_group(&section, &mut warnings);
+            groups.insert(Yaml::String(name.to_string()), Yaml::Array(members));
+        } else {
+            warnings.push(format!("[{}]: section not supported", section.name));
+        }
+    }
+    let mut root = Hash::new();
+    root.insert(Yaml::String("programs".to_string()), Yaml::Hash(programs));
+    if !groups.is_empty() {
+        root.insert(Yaml::String("groups".to_string()), Yaml::Hash(groups));
+    }
+    let mut yaml = String::new();
+    YamlEmitter::new(&mut yaml).dump(&Yaml::Hash(root)).expect("config should be serializable");
+    yaml.push('\n');
+    if let Err(errors) = yaml.parse::<Config>() {
+        warnings.extend(errors.0.iter().map(|e| format!("converted config: {}", e)));
+    }
+    (yaml, warnings)
+}
+
+/// Split an INI file into sections, dropping comments and joining
+/// continuation lines.
+fn parse_ini(ini: &str, warnings: &mut Vec<String>) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, line) in ini.lines().enumerate() {
+        let content = strip_comment(line);
+        if content.trim().is_empty() {
+            continue;
+        }
+        let trimmed = content.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            let name = trimmed[1..trimmed.len() - 1].trim().to_string();
+            sections.push(Section { name, options: Vec::new() });
+            continue;
+        }
+        let section = match sections.last_mut() {
+            Some(s) => s,
+            None => {
+                warnings.push(format!("line {}: option outside of a section", i + 1));
+                continue;
+            }
+        };
+        if content.starts_with(char::is_whitespace) {
+            if let Some((_, value)) = section.options.last_mut() {
+                value.push('\n');
+                value.push_str(trimmed);
+                continue;
+            }
+        }
+        match trimmed.find(['=', ':']) {
+            Some(n) => {
+                let key = trimmed[..n].trim().to_string();
+                let value = trimmed[n + 1..].trim().to_string();
+                section.options.push((key, value));
+            }
+            None => warnings.push(format!("line {}: expected `key = value`", i + 1)),
+        }
+    }
+    sections
+}
+
+/// Remove a comment: a line starting with `;` or `#`, or an inline `;`
+/// following a whitespace.
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with([';', '#']) {
+        return "";
+    }
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        if c == ';' && prev.is_some_and(char::is_whitespace) {
+            return &line[..i];
+        }
+        prev = Some(c);
+    }
+    line
+}
+
+fn import_program(section: &Section, warnings: &mut Vec<String>) -> Hash {
+    let mut program = Hash::new();
+    for (key, value) in &section.options {
+        let warn = |msg: &str| format!("[{}]: `{}`: {}", section.name, key, msg);
+        let translated = match key.as_str() {
+            "environment" => match parse_environment(value) {
+                Some(env) => {
+                    let env = env.into_iter().map(|(k, v)| (Yaml::String(k), Yaml::String(v)));
+                    Ok(("env", Yaml::Hash(env.collect())))
+                }
+                None => Err(warn("invalid value")),
+            },
+            _ => match RENAMED.iter().find(|(k, _)| k == key) {
+                Some((_, field)) => convert_value(field, value).map(|v| (*field, v)).ok_or_else(|| warn("invalid value")),
+                None => Err(warn("option not supported")),
+            },
+        };
+        match translated {
+            Ok((field, value)) => {
+                program.insert(Yaml::String(field.to_string()), value);
+            }
+            Err(warning) => warnings.push(warning),
+        }
+    }
+    program
+}
+
+fn import_group(section: &Section, warnings: &mut Vec<String>) -> Vec<Yaml> {
+    let mut members = Vec::new();
+    for (key, value) in &section.options {
+        if key == "programs" {
+            members.extend(value.split(',').map(|p| Yaml::String(p.trim().to_string())));
+        } else {
+            warnings.push(format!("[{}]: `{}`: option not supported", section.name, key));
+        }
+    }
+    members
+}
+
+/// Value of a taskmaster field from the supervisord one.
+fn convert_value(field: &str, value: &str) -> Option<Yaml> {
+    match field {
+        "numprocs" | "numprocs_start" | "startretries" | "starttime" | "stoptime" | "priority" => {
+            value.parse().ok().map(Yaml::Integer)
+        }
+        // taskmaster reads the digits of the umask as octal
+        "umask" => value.parse().ok().map(Yaml::Integer),
+        "autostart" => parse_bool(value).map(Yaml::Boolean),
+        "autorestart" => match value.to_lowercase().as_str() {
+            "unexpected" => Some(Yaml::String("unexpected".to_string())),
+            v => parse_bool(v).map(|b| Yaml::String(if b { "always" } else { "never" }.to_string())),
+        },
+        "exitcodes" => value
+            .split(',')
+            .map(|c| c.trim().parse().ok().map(Yaml::Integer))
+            .collect::<Option<Vec<Yaml>>>()
+            .map(Yaml::Array),
+        "stopsignal" => Some(Yaml::String(value.trim_start_matches("SIG").to_string())),
+        _ => Some(Yaml::String(value.to_string())),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse `KEY="value",KEY2=value2`, where quoted values may contain commas.
+fn parse_environment(value: &str) -> Option<Vec<(String, String)>> {
+    let mut env = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        let key = key.trim().to_string();
+        if key.is_empty() {
+            return None;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut val = String::new();
+        match chars.peek() {
+            Some(&q) if q == '"' || q == '\'' => {
+                chars.next();
+                val.extend(chars.by_ref().take_while(|&c| c != q));
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                if chars.next_if_eq(&',').is_none() && chars.peek().is_some() {
+                    return None;
+                }
+            }
+            _ => val = chars.by_ref().take_while(|&c| c != ',').collect::<String>().trim().to_string(),
+        }
+        env.push((key, val));
+        if chars.peek().is_none() {
+            return Some(env);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::import;
+    use crate::cfg::{Config, RestartPolicy};
+
+    #[test]
+    fn import_supervisord() {
+        let ini = "
+[supervisord]
+logfile = /tmp/supervisord.log
+
+# comment
+[program:worker]
+command = /bin/cat --verbose #x ; comment
+process_name = %(program_name)s_%(process_num)02d
+numprocs = 2
+umask = 022
+autorestart = true
+exitcodes = 0,2
+startsecs = 5
+stopwaitsecs = 20
+stopsignal = INT
+environment = LANG=\"C\",PATH=\"/bin,/usr/bin\"
+user = nobody
+
+[group:workers]
+programs = worker
+";
+        let (yaml, warnings) = import(ini);
+        let c: Config = yaml.parse().unwrap();
+        let p = &c.programs["worker_00"];
+        assert_eq!(p.cmd, "/bin/cat --verbose #x");
+        assert_eq!(p.umask, 0o22);
+        assert_eq!(p.autorestart, RestartPolicy::Always);
+        assert_eq!(p.exitcodes, vec![0, 2]);
+        assert_eq!((p.starttime, p.stoptime), (5, 20));
+        assert_eq!(p.env["PATH"], "/bin,/usr/bin");
+        assert_eq!(c.groups["workers"].len(), 2);
+        assert_eq!(warnings, vec![
+            "[supervisord]: section not supported",
+            "[program:worker]: `user`: option not supported",
+        ]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Export of taskmaster programs as systemd units.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{Config, ConfigErrors, LogPath, ProgramConfig, RestartPolicy};
+
+/// Stands for the process number of templated units until they are escaped,
+/// where it becomes the `%i` instance specifier.
+const INSTANCE: &str = "\0";
+
+/// Name and content of the unit files of a config: a `<name>.service` unit
+/// per program with a single instance, and a `<program>@.service` template
+/// for the others, instantiated with the process numbers, which therefore
+/// cannot be padded.
+pub fn units(conf: &Config) -> Result<Vec<(String, String)>, ConfigErrors> {
+    let mut units = Vec::new();
+    let mut names: Vec<&String> = conf.programs.keys().collect();
+    names.sort();
+    for name in names {
+        let program = &conf.programs[name];
+        if program.numprocs == 1 {
+            units.push((format!("{}.service", name), unit(program)));
+        } else if !units.iter().any(|(u, _)| *u == format!("{}@.service", program.group)) {
+            let template = conf.template(&program.group, INSTANCE)?;
+            units.push((format!("{}@.service", program.group), unit(&template)));
+        }
+    }
+    units.sort();
+    Ok(units)
+}
+
+/// Write the unit files of a config to `dir`, returning their paths.
+pub fn export(conf: &Config, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let units = units(conf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for (name, content) in units {
+        let path = dir.join(name);
+        fs::write(&path, content)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn unit(conf: &ProgramConfig) -> String {
+    let mut unit = String::from("[Unit]\n");
+    unit += &format!("Description=taskmaster program {}\n", escape(&conf.group));
+    unit += &format!("StartLimitBurst={}\n", conf.startretries);
+    unit += "\n[Service]\n";
+    unit += &format!("ExecStart={}\n", escape(&conf.cmd).replace('$', "$$"));
+    if let Some(wd) = &conf.workingdir {
+        unit += &format!("WorkingDirectory={}\n", escape(wd));
+    }
+    let mut env: Vec<_> = conf.env.iter().collect();
+    env.sort();
+    for (k, v) in env {
+        let var = format!("{}={}", k, v).replace('\\', "\\\\").replace('"', "\\\"");
+        unit += &format!("Environment=\"{}\"\n", escape(&var));
+    }
+    unit += &format!("UMask={:04o}\n", conf.umask);
+    unit += &format!("KillSignal={}\n", conf.stopsignal);
+    unit += &format!("TimeoutStopSec={}\n", conf.stoptime);
+    let restart = match conf.autorestart {
+        RestartPolicy::Always => "always",
+        RestartPolicy::Never => "no",
+        RestartPolicy::Unexpected => "on-failure",
+    };
+    unit += &format!("Restart={}\n", restart);
+    let codes: Vec<String> = conf.exitcodes.iter().map(i64::to_string).collect();
+    unit += &format!("SuccessExitStatus={}\n", codes.join(" "));
+    unit += &log_output("StandardOutput", &conf.stdout);
+    unit += &log_output("StandardError", &conf.stderr);
+    if conf.autostart {
+        unit += "\n[Install]\nWantedBy=multi-user.target\n";
+    }
+    unit
+}
+
+fn log_output(key: &str, path: &LogPath) -> String {
+    match path {
+        LogPath::Path(p) => format!("{}=append:{}\n", key, escape(p)),
+        LogPath::Non => format!("{}=null\n", key),
+        LogPath::Auto => String::new(),
+    }
+}
+
+/// Escape the `%` specifiers of systemd.
+fn escape(value: &str) -> String {
+    value.replace('%', "%%").replace(INSTANCE, "%i")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export, units};
+    use crate::cfg::Config;
+    use std::fs;
+
+    #[test]
+    fn export_units() {
+        let c: Config = "
+programs:
+  web:
+    cmd: \"/usr/bin/web --port 80%\"
+    workingdir: /srv
+    umask: 022
+    autorestart: always
+    stopsignal: INT
+    env:
+      MODE: prod
+  worker:
+    cmd: \"/usr/bin/worker --id %(process_num)d\"
+    numprocs: 3
+    autostart: false
+    stdout: /var/log/worker_%(process_num)d.log"
+            .parse()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("taskmaster-systemd-{}", std::process::id()));
+        let paths = export(&c, &dir).unwrap();
+        assert_eq!(paths, vec![dir.join("web.service"), dir.join("worker@.service")]);
+        let web = fs::read_to_string(dir.join("web.service")).unwrap();
+        assert!(web.contains("ExecStart=/usr/bin/web --port 80%%\n"));
+        assert!(web.contains("WorkingDirectory=/srv\n"));
+        assert!(web.contains("Environment=\"MODE=prod\"\n"));
+        assert!(web.contains("UMask=0022\n"));
+        assert!(web.contains("KillSignal=SIGINT\n"));
+        assert!(web.contains("Restart=always\n"));
+        assert!(web.contains("WantedBy=multi-user.target\n"));
+        let worker = fs::read_to_string(dir.join("worker@.service")).unwrap();
+        assert!(worker.contains("ExecStart=/usr/bin/worker --id %i\n"));
+        assert!(worker.contains("StandardOutput=append:/var/log/worker_%i.log\n"));
+        assert!(worker.contains("Restart=on-failure\n"));
+        assert!(!worker.contains("[Install]"));
+        fs::remove_dir_all(dir).unwrap();
+        let c: Config = "
+programs:
+  worker:
+    cmd: /usr/bin/worker
+    numprocs: 2
+    stdout: /var/log/worker_%(process_num)02d.log"
+            .parse()
+            .unwrap();
+        let e = units(&c).unwrap_err();
+        assert_eq!(e.0[0].field.as_deref(), Some("stdout"));
+    }
+}